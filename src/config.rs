@@ -6,7 +6,7 @@ use crate::middleware::rate_limiting::RateLimitingConfig;
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, str::FromStr};
 
-use crate::worker::{screenshot, pdf};
+use crate::worker::{animation, consent, emulation, screenshot, pdf, watermark};
 
 lazy_static! {
     pub static ref SERVER_CONFIG: ServerConfig = {
@@ -86,6 +86,10 @@ pub struct HttpConfig {
     pub listen: String,
     #[serde(default = "default_http_rate_limiting")]
     pub rate_limiting: RateLimitingConfig,
+    /// serves uploaded bundles to the browser, has to be reachable from it
+    /// and shouldn't be from anywhere else
+    #[serde(default = "default_http_internal_listen")]
+    pub internal_listen: String,
 }
 
 impl Default for HttpConfig {
@@ -93,6 +97,7 @@ impl Default for HttpConfig {
         HttpConfig {
             listen: default_http_listen(),
             rate_limiting: default_http_rate_limiting(),
+            internal_listen: default_http_internal_listen(),
         }
     }
 }
@@ -109,6 +114,30 @@ pub struct Bucket {
     pub screenshot_task_params: Option<screenshot::ScreenshotRequestParams>,
     #[serde(default = "pdf::default_buckets_pdf_task_params")]
     pub pdf_task_params: Option<pdf::PDFRequestParams>,
+    #[serde(default = "animation::default_buckets_animation_task_params")]
+    pub animation_task_params: Option<animation::AnimationRequestParams>,
+    #[serde(default)]
+    pub devices: HashMap<String, emulation::Device>,
+    /// EasyList/uBlock style lists for `block_ads`, `file://` paths are
+    /// read from the local disk, others from the bucket storage
+    #[serde(default)]
+    pub filter_lists: Vec<String>,
+    /// added to the built-in rules of `hide_cookie_banners`
+    #[serde(default)]
+    pub cookie_banners: consent::CookieBannerRules,
+    /// composited onto screenshots, see `watermark::Watermark::resolve`
+    #[serde(default)]
+    pub watermark: Option<watermark::WatermarkConfig>,
+    /// ttf or otf in the bucket storage for the labels of `viewports` composites,
+    /// which can't be made without it
+    #[serde(default)]
+    pub label_font: Option<String>,
+    /// css in the bucket storage for `markdown` documents, a built-in one if unset
+    #[serde(default)]
+    pub markdown_stylesheet: Option<String>,
+    /// directory in the bucket storage holding the `/render` templates
+    #[serde(default = "default_buckets_template_dir")]
+    pub template_dir: String,
 }
 
 impl Default for Bucket {
@@ -120,6 +149,14 @@ impl Default for Bucket {
             dal: dal.clone(),
             screenshot_task_params: screenshot::default_buckets_screenshot_task_params(),
             pdf_task_params: pdf::default_buckets_pdf_task_params(),
+            animation_task_params: animation::default_buckets_animation_task_params(),
+            devices: HashMap::new(),
+            filter_lists: vec![],
+            cookie_banners: consent::CookieBannerRules::default(),
+            watermark: None,
+            label_font: None,
+            markdown_stylesheet: None,
+            template_dir: default_buckets_template_dir(),
         }
     }
 }
@@ -194,6 +231,10 @@ fn default_http_listen() -> String {
     "0.0.0.0:2023".to_owned()
 }
 
+fn default_http_internal_listen() -> String {
+    "127.0.0.1:2024".to_owned()
+}
+
 fn default_http_rate_limiting() -> RateLimitingConfig {
     RateLimitingConfig::QPS(100)
 }
@@ -211,3 +252,7 @@ fn default_buckets_dal() -> HashMap<String, String> {
     map.insert("root".to_string(), "./static/shared".to_string());
     map
 }
+
+fn default_buckets_template_dir() -> String {
+    "templates/".to_owned()
+}
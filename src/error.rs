@@ -1,4 +1,6 @@
+use chromiumoxide::error::CdpError;
 use thiserror::Error;
+use tide::StatusCode;
 
 #[derive(Error, Debug)]
 pub enum GlobalError {
@@ -7,3 +9,45 @@ pub enum GlobalError {
     #[error("unknown error")]
     Unknown,
 }
+
+#[derive(Error, Debug)]
+pub enum WorkerError {
+    #[error("invalid parameter {name:?} {value:?}")]
+    InvalidParam { name: String, value: String },
+    #[error("timed out after {0}ms waiting for {1}")]
+    Timeout(u64, String),
+    #[error("no visible element matches selector {0:?}")]
+    SelectorNotFound(String),
+    #[error("action {index} ({action}) failed: {reason}")]
+    Action {
+        index: usize,
+        action: String,
+        reason: String,
+    },
+    #[error("no template {0:?}")]
+    TemplateNotFound(String),
+    #[error("{0}")]
+    Template(#[from] handlebars::RenderError),
+    #[error("{0}")]
+    Cdp(#[from] CdpError),
+    #[error("{0}")]
+    Storage(#[from] opendal::Error),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Image(#[from] image::ImageError),
+}
+
+impl WorkerError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            WorkerError::InvalidParam { .. } => StatusCode::BadRequest,
+            WorkerError::Timeout(..) => StatusCode::GatewayTimeout,
+            WorkerError::SelectorNotFound(_) => StatusCode::UnprocessableEntity,
+            WorkerError::Action { .. } => StatusCode::UnprocessableEntity,
+            WorkerError::TemplateNotFound(_) => StatusCode::NotFound,
+            WorkerError::Template(_) => StatusCode::UnprocessableEntity,
+            _ => StatusCode::InternalServerError,
+        }
+    }
+}
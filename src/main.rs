@@ -1,10 +1,16 @@
 #![feature(async_closure)]
 
 use futures::{channel::mpsc::channel, join, StreamExt};
+use std::collections::HashMap;
 use std::path::Path;
 use std::{fs::create_dir_all, process};
 
 use chromiumoxide::browser::{Browser, BrowserConfig};
+use chromiumoxide::Page;
+use chromiumoxide_cdp::cdp::browser_protocol::browser::BrowserContextId;
+use chromiumoxide_cdp::cdp::browser_protocol::target::{
+    CreateBrowserContextParams, CreateTargetParams,
+};
 
 use tide_tracing::TraceMiddleware;
 
@@ -22,6 +28,9 @@ use config::SERVER_CONFIG;
 use middleware::rate_limiting::{IpRateLimitingMiddleware, NSRateLimitingMiddleware};
 use worker::screenshot::{screenshot, ScreenshotWorker};
 use worker::pdf::{pdf, PDFWorker};
+use worker::animation::{animation, AnimationWorker};
+use worker::template::render;
+use worker::bundle::{bundle, serve as serve_bundle};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
@@ -63,19 +72,39 @@ async fn main() -> Result<(), std::io::Error> {
 
     tokio::task::spawn(async move {
         let (tx, mut rx) = channel(1);
+        let mut browser_contexts = HashMap::new();
         for id in 0..SERVER_CONFIG.browser.pool_size.into() {
-            ScreenshotWorker::new(id, browser.new_page("about:blank").await.unwrap(), tx.clone()).await;
+            let (browser_context_id, page) = new_isolated_page(&browser).await;
+            browser_contexts.insert(id, browser_context_id.clone());
+            ScreenshotWorker::new(id, page, browser_context_id, tx.clone()).await;
         }
 
-        PDFWorker::new((SERVER_CONFIG.browser.pool_size + 1).into(), browser.new_page("about:blank").await.unwrap(), tx.clone()).await;
+        let pdf_worker_id = (SERVER_CONFIG.browser.pool_size + 1).into();
+        let (browser_context_id, page) = new_isolated_page(&browser).await;
+        browser_contexts.insert(pdf_worker_id, browser_context_id.clone());
+        PDFWorker::new(pdf_worker_id, page, browser_context_id, tx.clone()).await;
+
+        // recordings grant no permissions, a page in the default context does
+        let animation_worker_id = pdf_worker_id + 1;
+        let page = browser.new_page("about:blank").await.unwrap();
+        AnimationWorker::new(animation_worker_id, page, tx.clone()).await;
 
         loop {
             let id = rx.next().await.unwrap();
-            let page = browser.new_page("about:blank").await.unwrap();
-            if id > SERVER_CONFIG.browser.pool_size.into() {
-                PDFWorker::new(id, page, tx.clone()).await;
+            if id == animation_worker_id {
+                let page = browser.new_page("about:blank").await.unwrap();
+                AnimationWorker::new(id, page, tx.clone()).await;
+                continue;
+            }
+            if let Some(browser_context_id) = browser_contexts.remove(&id) {
+                let _ = browser.dispose_browser_context(browser_context_id).await;
+            }
+            let (browser_context_id, page) = new_isolated_page(&browser).await;
+            browser_contexts.insert(id, browser_context_id.clone());
+            if id == pdf_worker_id {
+                PDFWorker::new(id, page, browser_context_id, tx.clone()).await;
             } else {
-                ScreenshotWorker::new(id, page, tx.clone()).await;
+                ScreenshotWorker::new(id, page, browser_context_id, tx.clone()).await;
             }
         }
     });
@@ -98,17 +127,38 @@ async fn main() -> Result<(), std::io::Error> {
         }
 
         info!("buckets {:?}", SERVER_CONFIG.buckets);
+        worker::filter_list::load().await?;
+        worker::watermark::load().await?;
+        worker::contact_sheet::load().await?;
         for (bucket, config) in &SERVER_CONFIG.buckets {
             DAL_OP_MAP.get(bucket).unwrap().create_dir("/").await?;
             let rate_limiting = NSRateLimitingMiddleware::from(&config.rate_limiting);
             app.at(format!("/screenshot/{:#}/", bucket).as_str())
                 .with(rate_limiting)
-                .get(|req| screenshot(req, bucket));
+                .get(|req| screenshot(req, bucket))
+                .post(|req| screenshot(req, bucket));
             
             let pdf_rate_limiting = NSRateLimitingMiddleware::from(&config.rate_limiting);
             app.at(format!("/pdf/{:#}/", bucket).as_str())
                 .with(pdf_rate_limiting)
-                .get(|req| pdf(req, bucket));
+                .get(|req| pdf(req, bucket))
+                .post(|req| pdf(req, bucket));
+
+            let animation_rate_limiting = NSRateLimitingMiddleware::from(&config.rate_limiting);
+            app.at(format!("/animation/{:#}/", bucket).as_str())
+                .with(animation_rate_limiting)
+                .get(|req| animation(req, bucket))
+                .post(|req| animation(req, bucket));
+
+            let render_rate_limiting = NSRateLimitingMiddleware::from(&config.rate_limiting);
+            app.at(format!("/render/{:#}/:template", bucket).as_str())
+                .with(render_rate_limiting)
+                .post(|req| render(req, bucket));
+
+            let bundle_rate_limiting = NSRateLimitingMiddleware::from(&config.rate_limiting);
+            app.at(format!("/bundle/{:#}/", bucket).as_str())
+                .with(bundle_rate_limiting)
+                .post(|req| bundle(req, bucket));
         }
 
         app.at("/static/")
@@ -132,7 +182,35 @@ async fn main() -> Result<(), std::io::Error> {
         app.listen(&SERVER_CONFIG.http.listen)
     };
 
-    let _ = join!(browser_handle, http_handle);
+    // only the browser talks to this one, it serves the unpacked bundles
+    let internal_handle = {
+        let mut app = tide::new();
+        app.at("/bundle/:token/").get(serve_bundle);
+        app.at("/bundle/:token/*path").get(serve_bundle);
+        app.listen(&SERVER_CONFIG.http.internal_listen)
+    };
+
+    let _ = join!(browser_handle, http_handle, internal_handle);
 
     Ok(())
 }
+
+/// Every pooled page gets a browser context of its own, so cookies, storage
+/// and permissions of one task can't show up in renders of another worker.
+async fn new_isolated_page(browser: &Browser) -> (BrowserContextId, Page) {
+    let browser_context_id = browser
+        .create_browser_context(CreateBrowserContextParams::default())
+        .await
+        .unwrap();
+    let page = browser
+        .new_page(
+            CreateTargetParams::builder()
+                .url("about:blank")
+                .browser_context_id(browser_context_id.clone())
+                .build()
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    (browser_context_id, page)
+}
@@ -1,4 +1,5 @@
 pub mod hash;
+pub mod param;
 pub mod pstree;
 pub mod signature_v4;
 pub mod time;
@@ -0,0 +1,38 @@
+/// Declares a request param that is kept as the string it was given in.
+///
+/// Params that parse into floats or tuples of them aren't `Hash`, but the
+/// request params are hashed into the cache key, so the string is stored and
+/// parsed again where it's used. `$valid` decides which strings are taken,
+/// `TryFrom<String>` turns the others into `WorkerError::InvalidParam` named
+/// `$name`.
+macro_rules! string_param {
+    ($(#[$attr:meta])* $vis:vis struct $type:ident, $name:literal, $valid:expr) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+        #[serde(try_from = "String", into = "String")]
+        $vis struct $type(String);
+
+        impl TryFrom<String> for $type {
+            type Error = $crate::error::WorkerError;
+
+            fn try_from(value: String) -> Result<Self, Self::Error> {
+                let valid: fn(&str) -> bool = $valid;
+                match valid(&value) {
+                    true => Ok($type(value)),
+                    false => Err($crate::error::WorkerError::InvalidParam {
+                        name: $name.to_owned(),
+                        value,
+                    }),
+                }
+            }
+        }
+
+        impl From<$type> for String {
+            fn from(value: $type) -> Self {
+                value.0
+            }
+        }
+    };
+}
+
+pub(crate) use string_param;
@@ -0,0 +1,268 @@
+use std::fmt;
+use std::time::Duration;
+
+use chromiumoxide::element::Element;
+use chromiumoxide::Page;
+use chromiumoxide_cdp::cdp::browser_protocol::page::NavigateParams;
+use tokio::time::{sleep, timeout};
+use url::Url;
+
+use crate::error::WorkerError;
+use crate::worker::scroll::ScrollTo;
+use crate::worker::wait::{navigate, wait_for_selector, WaitUntil};
+
+/// Time limit of a single step, in milliseconds.
+pub const DEFAULT_ACTION_TIMEOUT: u64 = 10_000;
+
+/// Largest `timeout` a step may set, in milliseconds.
+pub const MAX_ACTION_TIMEOUT: u64 = 30_000;
+
+/// Most steps in one request.
+pub const MAX_ACTIONS: usize = 50;
+
+/// Time limit of all steps together, in milliseconds.
+pub const MAX_ACTIONS_TIMEOUT: u64 = 60_000;
+
+/// One interaction, as in the `actions` array of a JSON request body,
+/// e.g. `{"type": "click", "selector": "#login"}`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    Click {
+        selector: String,
+    },
+    /// focuses `selector` and types `text` key by key
+    Type {
+        selector: String,
+        text: String,
+    },
+    /// key name like `Enter`, `Tab` or `ArrowDown`, sent to `selector` if set,
+    /// otherwise to whatever has focus
+    Press {
+        key: String,
+        #[serde(default)]
+        selector: Option<String>,
+    },
+    Hover {
+        selector: String,
+    },
+    /// scrolls `selector` into view, or the window to `x`, `y`
+    Scroll {
+        #[serde(default)]
+        selector: Option<String>,
+        #[serde(default)]
+        x: u32,
+        #[serde(default)]
+        y: u32,
+    },
+    WaitForSelector {
+        selector: String,
+    },
+    Wait {
+        ms: u64,
+    },
+    /// js expression, returned promises are awaited
+    Evaluate {
+        expression: String,
+    },
+    Navigate {
+        url: Url,
+        #[serde(default)]
+        wait_until: WaitUntil,
+    },
+}
+
+impl fmt::Display for Action {
+    /// Leaves typed text out, it may well be a password.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Click { selector } => write!(f, "click {:?}", selector),
+            Action::Type { selector, .. } => write!(f, "type into {:?}", selector),
+            Action::Press { key, .. } => write!(f, "press {:?}", key),
+            Action::Hover { selector } => write!(f, "hover {:?}", selector),
+            Action::Scroll {
+                selector: Some(selector),
+                ..
+            } => write!(f, "scroll to {:?}", selector),
+            Action::Scroll { x, y, .. } => write!(f, "scroll to {},{}", x, y),
+            Action::WaitForSelector { selector } => write!(f, "wait for {:?}", selector),
+            Action::Wait { ms } => write!(f, "wait {}ms", ms),
+            Action::Evaluate { .. } => write!(f, "evaluate"),
+            Action::Navigate { url, .. } => write!(f, "navigate to {}", url),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ActionStep {
+    #[serde(flatten)]
+    pub action: Action,
+    /// milliseconds, `DEFAULT_ACTION_TIMEOUT` unless set
+    #[serde(default)]
+    pub timeout: Option<u64>,
+}
+
+/// Checks `steps` against `MAX_ACTIONS` and `MAX_ACTION_TIMEOUT`.
+pub fn validate(steps: &[ActionStep]) -> Result<(), WorkerError> {
+    if steps.len() > MAX_ACTIONS {
+        return Err(WorkerError::InvalidParam {
+            name: "actions".to_owned(),
+            value: format!("{} steps", steps.len()),
+        });
+    }
+    match steps
+        .iter()
+        .find(|step| step.timeout.map_or(false, |ms| ms > MAX_ACTION_TIMEOUT))
+    {
+        Some(step) => Err(WorkerError::InvalidParam {
+            name: "timeout".to_owned(),
+            value: format!("{} of {}", step.timeout.unwrap(), step.action),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Runs the steps in order and stops at the first failing one, or once
+/// they took `MAX_ACTIONS_TIMEOUT` altogether.
+pub async fn run(page: &Page, steps: &[ActionStep]) -> Result<(), WorkerError> {
+    validate(steps)?;
+    timeout(
+        Duration::from_millis(MAX_ACTIONS_TIMEOUT),
+        run_steps(page, steps),
+    )
+    .await
+    .map_err(|_| WorkerError::Timeout(MAX_ACTIONS_TIMEOUT, "the actions".to_owned()))?
+}
+
+async fn run_steps(page: &Page, steps: &[ActionStep]) -> Result<(), WorkerError> {
+    for (index, step) in steps.iter().enumerate() {
+        let step_timeout = step.timeout.unwrap_or(DEFAULT_ACTION_TIMEOUT);
+        let failed = |reason: String| WorkerError::Action {
+            index,
+            action: step.action.to_string(),
+            reason,
+        };
+        match timeout(
+            Duration::from_millis(step_timeout),
+            perform(page, &step.action, step_timeout),
+        )
+        .await
+        {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => return Err(failed(err.to_string())),
+            Err(_) => return Err(failed(format!("timed out after {}ms", step_timeout))),
+        }
+    }
+    Ok(())
+}
+
+async fn perform(page: &Page, action: &Action, step_timeout: u64) -> Result<(), WorkerError> {
+    match action {
+        Action::Click { selector } => {
+            find(page, selector).await?.click().await?;
+        }
+        Action::Type { selector, text } => {
+            find(page, selector)
+                .await?
+                .focus()
+                .await?
+                .type_str(text)
+                .await?;
+        }
+        Action::Press { key, selector } => {
+            let element = match selector {
+                Some(selector) => {
+                    let element = find(page, selector).await?;
+                    element.focus().await?;
+                    element
+                }
+                // key events go to the focused element anyway
+                None => page.find_element("html").await?,
+            };
+            element.press_key(key).await?;
+        }
+        Action::Hover { selector } => {
+            find(page, selector).await?.hover().await?;
+        }
+        Action::Scroll {
+            selector: Some(selector),
+            ..
+        } => {
+            find(page, selector).await?.scroll_into_view().await?;
+        }
+        Action::Scroll { x, y, .. } => {
+            ScrollTo::Offset(*x, *y).apply(page).await?;
+        }
+        Action::WaitForSelector { selector } => wait_for_selector(page, selector).await,
+        Action::Wait { ms } => sleep(Duration::from_millis(*ms)).await,
+        Action::Evaluate { expression } => {
+            page.evaluate_expression(expression.as_str()).await?;
+        }
+        Action::Navigate { url, wait_until } => {
+            navigate(
+                page,
+                NavigateParams::new(url.to_string()),
+                None,
+                wait_until,
+                step_timeout,
+                async { Ok(()) },
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Waits for `selector` to show up, the step timeout bounds the wait.
+async fn find(page: &Page, selector: &str) -> Result<Element, WorkerError> {
+    wait_for_selector(page, selector).await;
+    Ok(page.find_element(selector).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn click(timeout: Option<u64>) -> ActionStep {
+        ActionStep {
+            action: Action::Click {
+                selector: "#ok".to_owned(),
+            },
+            timeout,
+        }
+    }
+
+    #[test]
+    fn caps_the_number_of_steps() {
+        assert!(validate(&[]).is_ok());
+        assert!(validate(&vec![click(None); MAX_ACTIONS]).is_ok());
+        let err = validate(&vec![click(None); MAX_ACTIONS + 1]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "invalid parameter \"actions\" \"{} steps\"",
+                MAX_ACTIONS + 1
+            )
+        );
+    }
+
+    #[test]
+    fn caps_step_timeouts() {
+        assert!(validate(&[click(Some(MAX_ACTION_TIMEOUT))]).is_ok());
+        let steps: Vec<ActionStep> = serde_json::from_str(
+            r##"[
+                {"type": "wait", "ms": 100},
+                {"type": "type", "selector": "#pw", "text": "secret", "timeout": 30001}
+            ]"##,
+        )
+        .unwrap();
+        match validate(&steps) {
+            // typed text stays out of the error
+            Err(WorkerError::InvalidParam { name, value }) => {
+                assert_eq!(name, "timeout");
+                assert_eq!(value, "30001 of type into \"#pw\"");
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+}
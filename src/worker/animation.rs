@@ -0,0 +1,405 @@
+use chromiumoxide::Page;
+
+use lazy_static::lazy_static;
+
+use tide::{http::Method, Error, Redirect, Request};
+
+use chromiumoxide_cdp::cdp::browser_protocol::page::NavigateParams;
+use futures::channel::mpsc::Sender;
+use futures::channel::oneshot::{channel as oneshot_channel, Sender as OneshotSender};
+
+use serde::{Deserialize, Serialize};
+
+use tide::log::debug;
+
+use url::Url;
+
+lazy_static! {
+    static ref ANIMATION_TASKS: TaskQueue<AnimationTask> = TaskQueue::new();
+}
+
+pub struct AnimationWorker {}
+
+impl AnimationWorker {
+    pub async fn new(id: usize, page: Page, ptx: Sender<usize>) {
+        debug!("worker {:#} create {:?}", id, page);
+        tokio::task::spawn(async move {
+            debug!("worker {:#} start", id);
+            loop {
+                if let Some(AnimationTask(tx, inner, navigate_params)) =
+                    ANIMATION_TASKS.next().await
+                {
+                    let _ = tx.send(worker(id, &page, inner, navigate_params).await);
+                }
+            }
+            let _ = ptx.try_send(id).unwrap();
+            let _ = page.close().await;
+            debug!("worker {:#} end", id);
+        });
+        debug!("worker {:#} created", id);
+    }
+}
+
+pub async fn worker(
+    id: usize,
+    page: &Page,
+    inner: AnimationTaskInner,
+    navigate_params: NavigateParams,
+) -> Result<TaskOutput, WorkerError> {
+    debug!(
+        "worker {:#} recv {:#} {:?}",
+        id, inner.filename, navigate_params
+    );
+    let result = record(id, page, inner, navigate_params).await;
+
+    let _ = page.goto("about:blank").await;
+    let _ = emulation::reset(page).await;
+    let _ = network::reset(page).await;
+    let _ = inject::reset(page).await;
+
+    result
+}
+
+async fn record(
+    id: usize,
+    page: &Page,
+    inner: AnimationTaskInner,
+    navigate_params: NavigateParams,
+) -> Result<TaskOutput, WorkerError> {
+    let op = DAL_OP_MAP.get(&inner.bucket).unwrap();
+    let filename = format!("{:#}.{:#}", inner.filename, inner.format.extension());
+
+    inner.device.emulate(page).await?;
+    emulation::override_user_agent(page, inner.device.user_agent.as_deref(), None).await?;
+    if inner.cookie_banners.is_some() {
+        inject::bypass_csp(page).await?;
+    }
+
+    navigate(
+        page,
+        navigate_params,
+        None,
+        &inner.wait_until,
+        inner.wait_timeout,
+        async {
+            if let Some(cookie_banners) = &inner.cookie_banners {
+                cookie_banners.dismiss(page).await?;
+            }
+            Ok::<(), WorkerError>(())
+        },
+    )
+    .await?;
+
+    if let Some(cookie_banners) = &inner.cookie_banners {
+        cookie_banners.accept(page).await?;
+    }
+
+    actions::run(page, &inner.actions).await?;
+
+    let frames = screencast::record(
+        page,
+        inner.fps,
+        inner.duration,
+        inner.max_size,
+        inner.scroll_by,
+    )
+    .await?;
+    let frame_count = frames.len();
+    let img_buf = screencast::encode(frames, inner.fps, inner.format)?;
+
+    let file_size = &img_buf.len();
+
+    op.write(&filename, img_buf).await?;
+
+    let signed_url = signed_url(op, &filename, &inner.bucket).await.unwrap();
+
+    debug!(
+        "worker {:#} save {:#} {:#} frames {:#}",
+        id, &filename, frame_count, file_size,
+    );
+
+    Ok(TaskOutput {
+        signed_url,
+        script_errors: vec![],
+        truncated: false,
+        variants: None,
+    })
+}
+
+pub async fn animation(mut req: Request<()>, bucket: &str) -> tide::Result {
+    let params: AnimationRequestQSParams = match req.method() {
+        Method::Post => req.body_json().await?,
+        _ => req.query()?,
+    };
+
+    let default_animation_task_params = &SERVER_CONFIG
+        .buckets
+        .get(bucket)
+        .unwrap()
+        .animation_task_params
+        .clone()
+        .unwrap();
+
+    let filename = params.filename();
+    let path = params.path(default_animation_task_params.format.unwrap());
+    let op = DAL_OP_MAP.get(bucket).unwrap();
+
+    let AnimationRequestQSParams {
+        url,
+        format,
+        fps,
+        duration,
+        max_size,
+        scroll_by,
+        width,
+        height,
+        device,
+        hide_cookie_banners,
+        actions,
+        wait_until,
+        wait_timeout,
+        ttl,
+    } = params;
+
+    if pool::is_fresh(op, &path, ttl).await {
+        let signed_url = signed_url(op, &path, bucket).await.unwrap();
+        return Ok(Redirect::new(signed_url).into());
+    }
+
+    let (tx, rx) = oneshot_channel();
+
+    let fps = fps.or(default_animation_task_params.fps).unwrap();
+    let duration = duration.or(default_animation_task_params.duration).unwrap();
+    let max_size = max_size.or(default_animation_task_params.max_size).unwrap();
+    screencast::validate(fps, duration, max_size)
+        .map_err(|err| Error::from_str(err.status(), err.to_string()))?;
+    actions::validate(&actions).map_err(|err| Error::from_str(err.status(), err.to_string()))?;
+
+    let wait_timeout = wait_timeout
+        .or(default_animation_task_params.wait_timeout)
+        .unwrap_or(DEFAULT_WAIT_TIMEOUT);
+    wait::validate_timeout(wait_timeout)
+        .map_err(|err| Error::from_str(err.status(), err.to_string()))?;
+
+    let device = Device::resolve(
+        bucket,
+        device
+            .as_deref()
+            .or(default_animation_task_params.device.as_deref()),
+        DeviceOverrides {
+            width: width.map(u32::from),
+            height: height.map(u32::from),
+            ..Default::default()
+        },
+        Device {
+            width: default_animation_task_params.width.unwrap().into(),
+            height: default_animation_task_params.height.unwrap().into(),
+            device_scale_factor: 1.0,
+            mobile: false,
+            touch: false,
+            user_agent: None,
+        },
+    )
+    .map_err(|err| Error::from_str(err.status(), err.to_string()))?;
+
+    ANIMATION_TASKS.push(AnimationTask {
+        0: tx,
+        1: AnimationTaskInner {
+            bucket: bucket.to_owned(),
+            filename,
+            device,
+            format: format.or(default_animation_task_params.format).unwrap(),
+            fps,
+            duration,
+            max_size,
+            scroll_by,
+            cookie_banners: hide_cookie_banners
+                .or(default_animation_task_params.hide_cookie_banners)
+                .unwrap_or(false)
+                .then(|| CookieBannerRules::for_bucket(bucket)),
+            actions,
+            wait_until: wait_until
+                .or_else(|| default_animation_task_params.wait_until.clone())
+                .unwrap_or_default(),
+            wait_timeout,
+        },
+        2: NavigateParams {
+            url: url.to_string(),
+            referrer: None,
+            transition_type: None,
+            frame_id: None,
+            referrer_policy: None,
+        },
+    });
+
+    pool::reply(rx).await
+}
+
+struct AnimationTaskInner {
+    bucket: String,
+    filename: String,
+    device: Device,
+    format: AnimationFormat,
+    fps: u8,
+    /// milliseconds
+    duration: u64,
+    /// pixels
+    max_size: u32,
+    /// css pixels to scroll down while recording
+    scroll_by: Option<u32>,
+    cookie_banners: Option<CookieBannerRules>,
+    actions: Vec<ActionStep>,
+    wait_until: WaitUntil,
+    wait_timeout: u64,
+}
+
+struct AnimationTask(
+    OneshotSender<Result<TaskOutput, WorkerError>>,
+    AnimationTaskInner,
+    NavigateParams,
+);
+
+use std::hash::Hash;
+
+use crate::config::{DAL_OP_MAP, SERVER_CONFIG};
+use crate::error::WorkerError;
+use crate::util::hash::{calculate_hash, calculate_hash_str};
+use crate::util::signature_v4::signed_url;
+use crate::worker::actions::{self, ActionStep};
+use crate::worker::consent::CookieBannerRules;
+use crate::worker::emulation::{self, Device, DeviceOverrides};
+use crate::worker::inject;
+use crate::worker::network;
+use crate::worker::output::TaskOutput;
+use crate::worker::pool::{self, TaskQueue};
+use crate::worker::screencast::{self, AnimationFormat};
+use crate::worker::wait::{self, navigate, WaitUntil, DEFAULT_WAIT_TIMEOUT};
+
+/// Query string of a GET, or the JSON body of a POST request.
+#[derive(Debug, Serialize, Deserialize, Clone, Hash)]
+pub struct AnimationRequestQSParams {
+    pub url: Url,
+
+    /// `webp`, `gif` or `apng`
+    pub format: Option<AnimationFormat>,
+    pub fps: Option<u8>,
+    /// milliseconds to record, at most `MAX_DURATION`
+    pub duration: Option<u64>,
+    /// largest width or height of the frames in pixels
+    pub max_size: Option<u32>,
+    /// css pixels to scroll down over the recording
+    pub scroll_by: Option<u32>,
+    pub ttl: Option<u64>,
+
+    pub width: Option<u16>,
+    pub height: Option<u16>,
+    /// preset name, see `emulation::Device::lookup`
+    pub device: Option<String>,
+
+    pub hide_cookie_banners: Option<bool>,
+    /// steps to run before recording, JSON body only
+    #[serde(default)]
+    pub actions: Vec<ActionStep>,
+
+    pub wait_until: Option<WaitUntil>,
+    /// milliseconds
+    pub wait_timeout: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Hash)]
+pub struct AnimationRequestParams {
+    #[serde(default = "default_format")]
+    pub format: Option<AnimationFormat>,
+    #[serde(default = "default_fps")]
+    pub fps: Option<u8>,
+    #[serde(default = "default_duration")]
+    pub duration: Option<u64>,
+    #[serde(default = "default_max_size")]
+    pub max_size: Option<u32>,
+    #[serde(default = "default_ttl")]
+    pub ttl: Option<u64>,
+
+    #[serde(default = "default_width")]
+    pub width: Option<u16>,
+    #[serde(default = "default_height")]
+    pub height: Option<u16>,
+    pub device: Option<String>,
+
+    pub hide_cookie_banners: Option<bool>,
+
+    #[serde(default = "default_wait_until")]
+    pub wait_until: Option<WaitUntil>,
+    #[serde(default = "default_wait_timeout")]
+    pub wait_timeout: Option<u64>,
+}
+
+impl AnimationRequestQSParams {
+    pub fn filename(&self) -> String {
+        format!(
+            "{:#}/{:x}",
+            calculate_hash_str(&self.url.origin().ascii_serialization()),
+            calculate_hash(self)
+        )
+    }
+
+    /// `default_format` is the bucket one.
+    pub fn path(&self, default_format: AnimationFormat) -> String {
+        format!(
+            "{:#}.{:#}",
+            self.filename(),
+            self.format.unwrap_or(default_format).extension()
+        )
+    }
+}
+
+pub fn default_buckets_animation_task_params() -> Option<AnimationRequestParams> {
+    Some(AnimationRequestParams {
+        format: default_format(),
+        fps: default_fps(),
+        duration: default_duration(),
+        max_size: default_max_size(),
+        ttl: default_ttl(),
+        width: default_width(),
+        height: default_height(),
+        device: None,
+        hide_cookie_banners: None,
+        wait_until: default_wait_until(),
+        wait_timeout: default_wait_timeout(),
+    })
+}
+
+fn default_format() -> Option<AnimationFormat> {
+    Some(AnimationFormat::Webp)
+}
+
+fn default_fps() -> Option<u8> {
+    Some(10)
+}
+
+fn default_duration() -> Option<u64> {
+    Some(3_000)
+}
+
+fn default_max_size() -> Option<u32> {
+    Some(640)
+}
+
+fn default_ttl() -> Option<u64> {
+    Some(60)
+}
+
+fn default_width() -> Option<u16> {
+    Some(1280)
+}
+
+fn default_height() -> Option<u16> {
+    Some(720)
+}
+
+fn default_wait_until() -> Option<WaitUntil> {
+    Some(WaitUntil::Load)
+}
+
+fn default_wait_timeout() -> Option<u64> {
+    Some(DEFAULT_WAIT_TIMEOUT)
+}
@@ -0,0 +1,162 @@
+use chromiumoxide_cdp::cdp::browser_protocol::network::ResourceType;
+
+use crate::worker::filter_list::AdFilter;
+
+/// Resource types that can be blocked, a subset of `Network.ResourceType`.
+/// Documents are never blocked by type.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ResourceKind {
+    Image,
+    Media,
+    Font,
+    Stylesheet,
+    Script,
+    /// `XMLHttpRequest`, `fetch()` and `EventSource`
+    Xhr,
+}
+
+impl ResourceKind {
+    fn matches(&self, resource_type: &ResourceType) -> bool {
+        matches!(
+            (self, resource_type),
+            (ResourceKind::Image, ResourceType::Image)
+                | (ResourceKind::Media, ResourceType::Media)
+                | (ResourceKind::Font, ResourceType::Font)
+                | (ResourceKind::Stylesheet, ResourceType::Stylesheet)
+                | (ResourceKind::Script, ResourceType::Script)
+                | (ResourceKind::Xhr, ResourceType::Xhr)
+                | (ResourceKind::Xhr, ResourceType::Fetch)
+                | (ResourceKind::Xhr, ResourceType::EventSource)
+        )
+    }
+}
+
+/// Requests to fail during a render, see `network::RequestOverrides::apply`.
+#[derive(Debug, Clone, Default)]
+pub struct Blocking {
+    pub resources: Vec<ResourceKind>,
+    /// globs matched against the whole url, `*` is any run of characters
+    /// and `?` any single one, e.g. `*://*.doubleclick.net/*`
+    pub urls: Vec<String>,
+    /// filter lists of the bucket, set by `block_ads`
+    pub ads: Option<&'static AdFilter>,
+    /// globs like `urls`, if set everything not matching one of them is blocked
+    pub allow_urls: Option<Vec<String>>,
+}
+
+impl Blocking {
+    pub fn is_empty(&self) -> bool {
+        self.resources.is_empty()
+            && self.urls.is_empty()
+            && self.ads.is_none()
+            && self.allow_urls.is_none()
+    }
+
+    /// `source_url` is the url of the rendered page, `main_frame` is set for
+    /// its own navigation.
+    pub fn blocks(
+        &self,
+        resource_type: &ResourceType,
+        main_frame: bool,
+        url: &str,
+        source_url: &str,
+    ) -> bool {
+        self.resources
+            .iter()
+            .any(|resource| resource.matches(resource_type))
+            || self.urls.iter().any(|pattern| glob_match(pattern, url))
+            || self
+                .allow_urls
+                .as_ref()
+                .map(|patterns| !patterns.iter().any(|pattern| glob_match(pattern, url)))
+                .unwrap_or(false)
+            || self
+                .ads
+                .map(|ads| ads.blocks(resource_type, main_frame, url, source_url))
+                .unwrap_or(false)
+    }
+}
+
+/// Wildcard match with backtracking to the last `*`, O(n·m) in the worst case.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_literally_without_wildcards() {
+        assert!(glob_match("", ""));
+        assert!(glob_match("https://example.com/", "https://example.com/"));
+        assert!(!glob_match("https://example.com/", "https://example.com/a"));
+        assert!(!glob_match("https://example.com/a", "https://example.com/"));
+        assert!(!glob_match("", "a"));
+        assert!(!glob_match("a", ""));
+    }
+
+    #[test]
+    fn star_matches_any_run() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("**", "anything"));
+        assert!(glob_match("*.png", "https://example.com/logo.png"));
+        assert!(!glob_match("*.png", "https://example.com/logo.png?v=2"));
+        assert!(glob_match("*.png*", "https://example.com/logo.png?v=2"));
+        assert!(glob_match(
+            "https://*.example.com/*",
+            "https://cdn.example.com/app.js"
+        ));
+        assert!(!glob_match(
+            "https://*.example.com/*",
+            "https://example.com/app.js"
+        ));
+    }
+
+    #[test]
+    fn star_backtracks() {
+        assert!(glob_match("*ab", "aab"));
+        assert!(glob_match("a*b*c", "abcbc"));
+        assert!(glob_match("*a*b*c*", "xaxbxc"));
+        assert!(!glob_match("*a*b*c*", "xcxbxa"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_char() {
+        assert!(glob_match("a?c", "abc"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(!glob_match("*?", ""));
+        assert!(glob_match("??", "éè"));
+    }
+
+    #[test]
+    fn stays_fast_on_failing_patterns() {
+        let text = "a".repeat(10_000);
+        assert!(!glob_match("*a*a*a*a*a*b", &text));
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use std::fs::{create_dir_all, File};
+use std::io::{self, Cursor, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+
+use bytes::Bytes;
+use flate2::read::GzDecoder;
+use futures::{stream, AsyncReadExt};
+use lazy_static::lazy_static;
+use percent_encoding::percent_decode_str;
+use tempfile::TempDir;
+use tide::{Body, Error, Request, StatusCode};
+use url::Url;
+use zip::ZipArchive;
+
+use crate::config::SERVER_CONFIG;
+use crate::error::WorkerError;
+use crate::util::hash::sha1_hex;
+use crate::worker::pdf::{self, PDFRequestQSParams};
+use crate::worker::screenshot::{self, ScreenshotRequestQSParams};
+
+/// Largest accepted upload in bytes.
+const MAX_BUNDLE_SIZE: u64 = 64 << 20;
+
+/// Largest unpacked bundle in bytes, guards against archive bombs.
+const MAX_UNPACKED_SIZE: u64 = 256 << 20;
+
+/// Most files in one bundle.
+const MAX_ENTRIES: usize = 10_000;
+
+lazy_static! {
+    /// Unpacked bundles by the sha1 of their archive, alive while a render uses them.
+    static ref BUNDLES: Mutex<HashMap<String, Weak<TempDir>>> = Mutex::new(HashMap::new());
+}
+
+/// Query string of a bundle upload.
+#[derive(Debug, Deserialize)]
+struct BundleQSParams {
+    /// `pdf`, or an image format of the screenshot endpoint
+    format: Option<String>,
+    /// fail every request leaving the bundle, WebSockets aren't intercepted
+    /// and still connect
+    block_network: Option<bool>,
+}
+
+/// An unpacked archive, served on `internal_listen` until the last handle
+/// to it is dropped, which removes the directory.
+///
+/// The same archive uploaded twice is served from the same url, so the
+/// captures of it share their cache key.
+pub struct Bundle {
+    token: String,
+    dir: Arc<TempDir>,
+}
+
+impl Bundle {
+    /// Unpacks a zip, tar or tar.gz archive into a temp directory of its own.
+    /// Entries leaving the directory, links and special files are skipped.
+    pub async fn unpack(archive: Vec<u8>) -> Result<Self, WorkerError> {
+        let token = sha1_hex(&archive);
+        if let Some(dir) = live(&token) {
+            return Ok(Bundle { token, dir });
+        }
+
+        let dir = tempfile::Builder::new().prefix("bundle-").tempdir()?;
+        let dir = tokio::task::spawn_blocking(move || {
+            extract(&archive, dir.path())
+                .map(|_| dir)
+                .map_err(|err| WorkerError::InvalidParam {
+                    name: "bundle".to_owned(),
+                    value: err.to_string(),
+                })
+        })
+        .await
+        .unwrap()?;
+
+        // an upload of the same archive may have finished meanwhile
+        let mut bundles = BUNDLES.lock().unwrap();
+        bundles.retain(|_, dir| dir.strong_count() > 0);
+        let dir = match bundles.get(&token).and_then(Weak::upgrade) {
+            Some(dir) => dir,
+            None => {
+                let dir = Arc::new(dir);
+                bundles.insert(token.clone(), Arc::downgrade(&dir));
+                dir
+            }
+        };
+        Ok(Bundle { token, dir })
+    }
+
+    /// Url of the bundle directory, ends with a slash.
+    fn root(&self) -> String {
+        format!(
+            "http://{}/bundle/{}/",
+            SERVER_CONFIG.http.internal_listen, self.token
+        )
+    }
+
+    /// Where the browser finds `entry`, a path relative to the bundle root.
+    pub fn url(&self, entry: &str) -> Result<Url, WorkerError> {
+        let invalid = || WorkerError::InvalidParam {
+            name: "entry".to_owned(),
+            value: entry.to_owned(),
+        };
+        let path = enclosed(Path::new(entry)).ok_or_else(invalid)?;
+        if !self.dir.path().join(&path).exists() {
+            return Err(invalid());
+        }
+        Url::parse(&self.root())
+            .and_then(|root| root.join(entry))
+            .map_err(|_| invalid())
+    }
+}
+
+fn live(token: &str) -> Option<Arc<TempDir>> {
+    BUNDLES.lock().unwrap().get(token).and_then(Weak::upgrade)
+}
+
+/// `path` without `.` components, `None` if it is absolute or has `..` ones.
+fn enclosed(path: &Path) -> Option<PathBuf> {
+    let mut enclosed = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => enclosed.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(enclosed)
+}
+
+fn extract(archive: &[u8], dir: &Path) -> io::Result<()> {
+    let mut budget = (MAX_ENTRIES, MAX_UNPACKED_SIZE);
+    if archive.starts_with(b"PK\x03\x04") {
+        let mut zip = ZipArchive::new(Cursor::new(archive))?;
+        for index in 0..zip.len() {
+            let mut file = zip.by_index(index)?;
+            // links are files holding their target in zips, told apart by the unix mode
+            if file.is_file() && !file.is_symlink() {
+                let path = PathBuf::from(file.name());
+                write_file(dir, &path, &mut file, &mut budget)?;
+            }
+        }
+    } else if archive.starts_with(&[0x1f, 0x8b]) {
+        extract_tar(GzDecoder::new(archive), dir, &mut budget)?;
+    } else {
+        extract_tar(archive, dir, &mut budget)?;
+    }
+    Ok(())
+}
+
+fn extract_tar(archive: impl Read, dir: &Path, budget: &mut (usize, u64)) -> io::Result<()> {
+    for entry in tar::Archive::new(archive).entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_file() {
+            let path = entry.path()?.into_owned();
+            write_file(dir, &path, &mut entry, budget)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes one file, `budget` is what's left of `MAX_ENTRIES` and `MAX_UNPACKED_SIZE`.
+fn write_file(
+    dir: &Path,
+    path: &Path,
+    reader: &mut impl Read,
+    budget: &mut (usize, u64),
+) -> io::Result<()> {
+    let path = match enclosed(path) {
+        Some(path) if !path.as_os_str().is_empty() => dir.join(path),
+        _ => return Ok(()),
+    };
+    let too_large = || io::Error::new(io::ErrorKind::InvalidData, "archive too large");
+    budget.0 = budget.0.checked_sub(1).ok_or_else(too_large)?;
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    let written = io::copy(&mut reader.take(budget.1 + 1), &mut File::create(path)?)?;
+    budget.1 = budget.1.checked_sub(written).ok_or_else(too_large)?;
+    Ok(())
+}
+
+/// The archive and entry of a `multipart/form-data` upload, in the `bundle`
+/// and `entry` fields. `entry` defaults to `index.html`.
+async fn read_upload(req: &mut Request<()>) -> tide::Result<(Vec<u8>, String)> {
+    let bad_request =
+        |err: &dyn std::fmt::Display| Error::from_str(StatusCode::BadRequest, err.to_string());
+    let boundary = req
+        .content_type()
+        .filter(|mime| mime.essence() == "multipart/form-data")
+        .and_then(|mime| {
+            mime.param("boundary")
+                .map(|boundary| boundary.as_str().to_owned())
+        })
+        .ok_or_else(|| bad_request(&"expected a multipart/form-data body"))?;
+
+    let mut body = vec![];
+    req.take_body()
+        .take(MAX_BUNDLE_SIZE + 1)
+        .read_to_end(&mut body)
+        .await?;
+    if body.len() as u64 > MAX_BUNDLE_SIZE {
+        return Err(Error::from_str(
+            StatusCode::PayloadTooLarge,
+            "bundle too large",
+        ));
+    }
+
+    let mut multipart = multer::Multipart::new(
+        stream::once(async move { Ok::<_, io::Error>(Bytes::from(body)) }),
+        boundary,
+    );
+    let (mut archive, mut entry) = (None, None);
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|err| bad_request(&err))?
+    {
+        let name = field.name().unwrap_or_default().to_owned();
+        match name.as_str() {
+            "bundle" => archive = Some(field.bytes().await.map_err(|err| bad_request(&err))?),
+            "entry" => entry = Some(field.text().await.map_err(|err| bad_request(&err))?),
+            _ => {}
+        }
+    }
+    let archive = archive.ok_or_else(|| bad_request(&"missing bundle field"))?;
+    Ok((
+        archive.to_vec(),
+        entry.unwrap_or_else(|| "index.html".to_owned()),
+    ))
+}
+
+/// `POST /bundle/{bucket}/`, renders a page of an uploaded static site.
+/// The query string takes the params of the screenshot or pdf endpoint,
+/// `url`, `html` and `markdown` are replaced by the bundle entry.
+pub async fn bundle(mut req: Request<()>, bucket: &str) -> tide::Result {
+    let BundleQSParams {
+        format,
+        block_network,
+    } = req.query()?;
+    let (archive, entry) = read_upload(&mut req).await?;
+    let bundle = Bundle::unpack(archive)
+        .await
+        .map_err(|err| Error::from_str(err.status(), err.to_string()))?;
+    let url = bundle
+        .url(&entry)
+        .map_err(|err| Error::from_str(err.status(), err.to_string()))?;
+    let allow_urls = block_network
+        .unwrap_or(false)
+        .then(|| vec![format!("{}*", bundle.root())]);
+
+    let response = match format.as_deref() {
+        Some("pdf") => {
+            let params: PDFRequestQSParams = req.query()?;
+            pdf::enqueue(
+                PDFRequestQSParams {
+                    url: Some(url),
+                    html: None,
+                    markdown: None,
+                    base_url: None,
+                    allow_urls: allow_urls.or(params.allow_urls),
+                    ..params
+                },
+                bucket,
+            )
+            .await
+        }
+        _ => {
+            let params: ScreenshotRequestQSParams = req.query()?;
+            screenshot::enqueue(
+                ScreenshotRequestQSParams {
+                    url: Some(url),
+                    html: None,
+                    markdown: None,
+                    base_url: None,
+                    allow_urls: allow_urls.or(params.allow_urls),
+                    ..params
+                },
+                bucket,
+            )
+            .await
+        }
+    };
+    // the browser is done with the files
+    drop(bundle);
+    response
+}
+
+/// Serves the files of live bundles on `internal_listen`, directories answer
+/// with their `index.html`.
+pub async fn serve(req: Request<()>) -> tide::Result {
+    let not_found = || Error::from_str(StatusCode::NotFound, "");
+    let dir = live(req.param("token")?).ok_or_else(not_found)?;
+    let path = percent_decode_str(req.param("path").unwrap_or_default()).decode_utf8()?;
+    let mut file = dir
+        .path()
+        .join(enclosed(Path::new(path.as_ref())).ok_or_else(not_found)?);
+    if file.is_dir() {
+        file.push("index.html");
+    }
+    Ok(Body::from_file(file).await.map_err(|_| not_found())?.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enclosed_paths() {
+        for (path, expected) in [
+            ("index.html", Some("index.html")),
+            ("./a/./b.html", Some("a/b.html")),
+            ("a//b/", Some("a/b")),
+            (".", Some("")),
+            ("", Some("")),
+            ("..", None),
+            ("../etc/passwd", None),
+            ("a/../b", None),
+            ("a/b/../../..", None),
+            ("/etc/passwd", None),
+            ("/", None),
+        ] {
+            assert_eq!(
+                enclosed(Path::new(path)).as_deref(),
+                expected.map(Path::new),
+                "{:?}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn writes_enclosed_files_within_budget() {
+        let dir = TempDir::new().unwrap();
+        let mut budget = (10, 100);
+
+        write_file(
+            dir.path(),
+            Path::new("a/b.txt"),
+            &mut &b"hello"[..],
+            &mut budget,
+        )
+        .unwrap();
+        assert_eq!(std::fs::read(dir.path().join("a/b.txt")).unwrap(), b"hello");
+        assert_eq!(budget, (9, 95));
+
+        // skipped, not an error, and nothing is spent
+        write_file(
+            dir.path(),
+            Path::new("../x.txt"),
+            &mut &b"x"[..],
+            &mut budget,
+        )
+        .unwrap();
+        write_file(dir.path(), Path::new("."), &mut &b"x"[..], &mut budget).unwrap();
+        assert!(!dir.path().join("../x.txt").exists());
+        assert_eq!(budget, (9, 95));
+    }
+
+    #[test]
+    fn stops_past_the_budget() {
+        let dir = TempDir::new().unwrap();
+        let err =
+            write_file(dir.path(), Path::new("big"), &mut &[0; 6][..], &mut (10, 5)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let err =
+            write_file(dir.path(), Path::new("one"), &mut &b"x"[..], &mut (0, 100)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+    #[test]
+    fn skips_zip_symlinks() {
+        use std::io::Write;
+        use zip::write::{SimpleFileOptions, ZipWriter};
+
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("index.html", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"<p>hi</p>").unwrap();
+        zip.add_symlink("passwd", "/etc/passwd", SimpleFileOptions::default())
+            .unwrap();
+        let archive = zip.finish().unwrap().into_inner();
+
+        let dir = TempDir::new().unwrap();
+        extract(&archive, dir.path()).unwrap();
+        assert!(dir.path().join("index.html").is_file());
+        assert!(!dir.path().join("passwd").exists());
+    }
+}
@@ -0,0 +1,209 @@
+use chromiumoxide::Page;
+
+use crate::config::SERVER_CONFIG;
+use crate::error::WorkerError;
+use crate::worker::inject::add_style;
+
+/// Containers of common consent management platforms.
+const HIDE_SELECTORS: &[&str] = &[
+    "#onetrust-consent-sdk",
+    "#onetrust-banner-sdk",
+    "#CybotCookiebotDialog",
+    "#CybotCookiebotDialogBodyUnderlay",
+    "#usercentrics-root",
+    "#didomi-host",
+    "#didomi-popup",
+    ".qc-cmp2-container",
+    "#qc-cmp2-container",
+    "[id^=\"sp_message_container\"]",
+    "#truste-consent-track",
+    ".truste_overlay",
+    ".truste_box_overlay",
+    "#consent_blackbar",
+    ".fc-consent-root",
+    "#cmpbox",
+    "#cmpbox2",
+    ".osano-cm-window",
+    "#hs-eu-cookie-confirmation",
+    "#cookie-law-info-bar",
+    "#cookie-notice",
+    ".cc-window",
+    ".cc-banner",
+    "#iubenda-cs-banner",
+    "#klaro",
+    ".klaro",
+    "#cookiescript_injected",
+    "#termly-code-snippet-support",
+    "#gdpr-cookie-message",
+    "#cookie-banner",
+    ".cookie-banner",
+    "#cookieConsent",
+    ".cookie-consent",
+];
+
+/// Scroll locks some platforms put on the document while their dialog is open.
+const UNLOCK_SELECTORS: &[&str] = &[
+    "html.sp-message-open",
+    "body.didomi-popup-open",
+    "body.qc-cmp-ui-showing",
+    "html.cmp-ui-showing",
+    "body.cookiebot-active",
+];
+
+/// "Accept" buttons of common consent management platforms.
+const ACCEPT_SELECTORS: &[&str] = &[
+    "#onetrust-accept-btn-handler",
+    "#CybotCookiebotDialogBodyLevelButtonLevelOptinAllowAll",
+    "#CybotCookiebotDialogBodyButtonAccept",
+    "#didomi-notice-agree-button",
+    ".qc-cmp2-summary-buttons button[mode=\"primary\"]",
+    "#truste-consent-button",
+    ".fc-cta-consent",
+    ".osano-cm-accept-all",
+    "#hs-eu-confirmation-button",
+    "#cookie_action_close_header",
+    ".iubenda-cs-accept-btn",
+    ".cm-btn-accept-all",
+    ".cc-allow",
+    ".cc-dismiss",
+];
+
+/// Labels of accept buttons, matched case-insensitively against the whole text
+/// of buttons inside something that looks like a banner or dialog.
+const ACCEPT_TEXTS: &[&str] = &[
+    "accept",
+    "accept all",
+    "accept all cookies",
+    "accept cookies",
+    "allow all",
+    "allow all cookies",
+    "agree",
+    "i agree",
+    "i accept",
+    "got it",
+    "alle akzeptieren",
+    "akzeptieren",
+    "alle cookies akzeptieren",
+    "zustimmen",
+    "tout accepter",
+    "accepter",
+    "accepter et fermer",
+    "j'accepte",
+    "aceptar",
+    "aceptar todo",
+    "aceptar todas",
+    "accetta",
+    "accetta tutto",
+    "accetto",
+    "aceitar",
+    "aceitar todos",
+    "alles accepteren",
+    "accepteren",
+    "akceptuję",
+    "zaakceptuj wszystkie",
+    "godkänn alla",
+    "accepter alle",
+    "hyväksy kaikki",
+];
+
+/// Clicks the first accept button found, by selector first and by label second.
+const ACCEPT_JS: &str = r#"
+((selectors, texts) => {
+    const banner = /cookie|consent|gdpr|privacy|cmp|notice/i;
+    const inBanner = (element) => {
+        for (let node = element; node && node !== document.body; node = node.parentElement) {
+            const names = [node.id, node.getAttribute('class'), node.getAttribute('aria-label')].join(' ');
+            if (node.getAttribute('role') === 'dialog' || banner.test(names)) return true;
+        }
+        return false;
+    };
+    let target = null;
+    for (const selector of selectors) {
+        try { target = document.querySelector(selector); } catch (e) {}
+        if (target) break;
+    }
+    if (!target) {
+        target = Array.from(document.querySelectorAll(
+            'button, a, [role="button"], input[type="button"], input[type="submit"]'
+        )).find((element) => {
+            const text = (element.innerText || element.value || '').trim().toLowerCase();
+            return texts.includes(text) && inBanner(element);
+        });
+    }
+    if (target) target.click();
+    return !!target;
+})
+"#;
+
+/// Selectors of consent dialogs to hide and of their accept buttons.
+///
+/// The built-in rules are always used, buckets can add their own under
+/// `cookie_banners` in `config.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CookieBannerRules {
+    #[serde(default)]
+    pub hide_selectors: Vec<String>,
+    #[serde(default)]
+    pub accept_selectors: Vec<String>,
+    #[serde(default)]
+    pub accept_texts: Vec<String>,
+}
+
+impl CookieBannerRules {
+    /// Built-in rules followed by the ones of `bucket`.
+    pub fn for_bucket(bucket: &str) -> Self {
+        let extra = SERVER_CONFIG
+            .buckets
+            .get(bucket)
+            .map(|config| config.cookie_banners.clone())
+            .unwrap_or_default();
+        let builtin = |rules: &[&str]| rules.iter().map(|rule| rule.to_string());
+        CookieBannerRules {
+            hide_selectors: builtin(HIDE_SELECTORS)
+                .chain(extra.hide_selectors)
+                .collect(),
+            accept_selectors: builtin(ACCEPT_SELECTORS)
+                .chain(extra.accept_selectors)
+                .collect(),
+            accept_texts: builtin(ACCEPT_TEXTS)
+                .chain(extra.accept_texts.iter().map(|text| text.to_lowercase()))
+                .collect(),
+        }
+    }
+
+    /// Clicks an accept button, so the site stores the consent and removes
+    /// its overlay, and hides whatever dialog is left. Returns whether a
+    /// button was clicked.
+    pub async fn dismiss(&self, page: &Page) -> Result<bool, WorkerError> {
+        let clicked = self.accept(page).await?;
+        // one rule per selector, a single invalid one would void a selector list
+        let css = self
+            .hide_selectors
+            .iter()
+            .map(|selector| format!("{} {{ display: none !important; }}", selector))
+            .chain(UNLOCK_SELECTORS.iter().map(|selector| {
+                format!(
+                    "{} {{ overflow: auto !important; position: static !important; }}",
+                    selector
+                )
+            }))
+            .collect::<Vec<_>>()
+            .join("\n");
+        add_style(page, &css).await?;
+        Ok(clicked)
+    }
+
+    /// Dialogs are often injected late, so this runs again right before capture.
+    pub async fn accept(&self, page: &Page) -> Result<bool, WorkerError> {
+        Ok(page
+            .evaluate_expression(format!(
+                "{}({}, {})",
+                ACCEPT_JS,
+                serde_json::to_string(&self.accept_selectors).unwrap(),
+                serde_json::to_string(&self.accept_texts).unwrap()
+            ))
+            .await?
+            .into_value::<bool>()
+            .unwrap_or(false))
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::OnceLock;
+
+use ab_glyph::{FontArc, PxScale};
+use image::{imageops, Rgba, RgbaImage};
+use imageproc::drawing::{draw_text_mut, text_size};
+use tide::log::info;
+
+use crate::config::{DAL_OP_MAP, SERVER_CONFIG};
+
+static LABEL_FONTS: OnceLock<HashMap<String, FontArc>> = OnceLock::new();
+
+/// Pixels around and between the tiles.
+const GAP: u32 = 32;
+
+/// Label height in pixels.
+const LABEL_SIZE: f32 = 28.0;
+
+const BACKGROUND: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const LABEL_COLOR: Rgba<u8> = Rgba([32, 32, 32, 255]);
+
+/// Whether the bucket has a `label_font`, composites are only made with one.
+pub fn has_labels(bucket: &str) -> bool {
+    LABEL_FONTS
+        .get()
+        .map_or(false, |fonts| fonts.contains_key(bucket))
+}
+
+/// Puts `tiles` side by side, top aligned, each under its label.
+/// Labels are left out if the bucket has no `label_font`, see `has_labels`.
+pub fn compose(bucket: &str, tiles: &[(String, RgbaImage)]) -> RgbaImage {
+    let font = LABEL_FONTS.get().and_then(|fonts| fonts.get(bucket));
+    let scale = PxScale::from(LABEL_SIZE);
+    let label_height = match font {
+        Some(_) => LABEL_SIZE as u32 + GAP / 2,
+        None => 0,
+    };
+
+    let width = tiles
+        .iter()
+        .map(|(_, tile)| tile.width() + GAP)
+        .sum::<u32>()
+        + GAP;
+    let height = tiles
+        .iter()
+        .map(|(_, tile)| tile.height())
+        .max()
+        .unwrap_or(0)
+        + label_height
+        + 2 * GAP;
+    let mut sheet = RgbaImage::from_pixel(width, height, BACKGROUND);
+
+    let mut x = GAP;
+    for (label, tile) in tiles {
+        if let Some(font) = font {
+            let (label_width, _) = text_size(scale, font, label);
+            // centered over the tile, cut off on the left if wider
+            let label_x = x as i32 + (tile.width() as i32 - label_width as i32).max(0) / 2;
+            draw_text_mut(
+                &mut sheet,
+                LABEL_COLOR,
+                label_x,
+                GAP as i32,
+                scale,
+                font,
+                label,
+            );
+        }
+        imageops::overlay(
+            &mut sheet,
+            tile,
+            i64::from(x),
+            i64::from(GAP + label_height),
+        );
+        x += tile.width() + GAP;
+    }
+    sheet
+}
+
+/// Reads the `label_font` of every bucket from the bucket storage.
+/// Runs once at startup.
+pub async fn load() -> io::Result<()> {
+    let mut fonts = HashMap::new();
+    for (bucket, config) in &SERVER_CONFIG.buckets {
+        if let Some(path) = &config.label_font {
+            let font = FontArc::try_from_vec(DAL_OP_MAP.get(bucket).unwrap().read(path).await?)
+                .map_err(|err| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("bucket {} label font {}: {}", bucket, path, err),
+                    )
+                })?;
+            info!("bucket {:#} label font {:#}", bucket, path);
+            fonts.insert(bucket.clone(), font);
+        }
+    }
+    let _ = LABEL_FONTS.set(fonts);
+    Ok(())
+}
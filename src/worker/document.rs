@@ -0,0 +1,248 @@
+use chromiumoxide::error::CdpError;
+use chromiumoxide::Page;
+use chromiumoxide_cdp::cdp::browser_protocol::page::SetDocumentContentParams;
+use opendal::Operator;
+use pulldown_cmark::{html, Options, Parser};
+use tide::Request;
+use url::Url;
+
+use crate::error::WorkerError;
+
+/// Stylesheet of markdown documents if the bucket has no `markdown_stylesheet`.
+const DEFAULT_STYLESHEET: &str = r#"
+body { max-width: 46em; margin: 2em auto; padding: 0 1em; font: 16px/1.6 -apple-system, "Segoe UI", Helvetica, Arial, sans-serif; color: #1f2328; }
+h1, h2 { padding-bottom: .3em; border-bottom: 1px solid #d1d9e0; }
+a { color: #0969da; }
+code, pre { font-family: ui-monospace, Menlo, Consolas, monospace; font-size: 85%; background: #f6f8fa; border-radius: 6px; }
+code { padding: .2em .4em; }
+pre { padding: 1em; overflow: auto; }
+pre code { padding: 0; background: none; }
+blockquote { margin: 0; padding: 0 1em; color: #59636e; border-left: .25em solid #d1d9e0; }
+table { border-collapse: collapse; }
+th, td { padding: 6px 13px; border: 1px solid #d1d9e0; }
+img { max-width: 100%; }
+"#;
+
+/// Document posted as the request body, in place of a `url`.
+pub enum Content {
+    Html(String),
+    Markdown(String),
+}
+
+impl Content {
+    /// Reads the body of `text/html` and `text/markdown` requests,
+    /// `None` for any other content type.
+    pub async fn read(req: &mut Request<()>) -> tide::Result<Option<Self>> {
+        let essence = match req.content_type() {
+            Some(mime) => mime.essence().to_owned(),
+            None => return Ok(None),
+        };
+        Ok(match essence.as_str() {
+            "text/html" => Some(Content::Html(req.body_string().await?)),
+            "text/markdown" => Some(Content::Markdown(req.body_string().await?)),
+            _ => None,
+        })
+    }
+}
+
+/// Picks what to load from the `url`, `html` and `markdown` params, exactly one has to be set.
+///
+/// Returns the url the request overrides apply to and the document to put
+/// into the page, if any. Documents always end up on `about:blank`, `base_url`
+/// only sets what their relative urls resolve against.
+pub async fn resolve(
+    op: &Operator,
+    markdown_stylesheet: &Option<String>,
+    url: Option<Url>,
+    html: Option<String>,
+    markdown: Option<String>,
+    base_url: Option<Url>,
+) -> Result<(String, Option<String>), WorkerError> {
+    let document = match (url, html, markdown) {
+        (Some(url), None, None) if base_url.is_none() => return Ok((url.to_string(), None)),
+        (None, Some(html), None) => html,
+        (None, None, Some(markdown)) => {
+            let stylesheet = match markdown_stylesheet {
+                Some(path) => String::from_utf8_lossy(&op.read(path).await?).into_owned(),
+                None => DEFAULT_STYLESHEET.to_owned(),
+            };
+            from_markdown(&markdown, &stylesheet)
+        }
+        (Some(url), None, None) => {
+            return Err(WorkerError::InvalidParam {
+                name: "base_url".to_owned(),
+                value: url.to_string(),
+            })
+        }
+        _ => {
+            return Err(WorkerError::InvalidParam {
+                name: "url".to_owned(),
+                value: "exactly one of url, html and markdown".to_owned(),
+            })
+        }
+    };
+    Ok(match base_url {
+        Some(base_url) => (base_url.to_string(), Some(with_base(document, &base_url))),
+        None => ("about:blank".to_owned(), Some(document)),
+    })
+}
+
+/// Rejects origin scoped params that can't take effect for a `document`, both
+/// lists pair their names with whether the request set them.
+///
+/// The document is shown on `about:blank`, whose opaque origin is granted no
+/// permissions and makes every request cross-site, so the `page` ones are
+/// rejected for all documents. The `requests` ones still reach the origin of
+/// the `base_url` and are only rejected without one.
+pub fn check_scoped(
+    url: &str,
+    document: bool,
+    page: &[(&str, bool)],
+    requests: &[(&str, bool)],
+) -> Result<(), WorkerError> {
+    if !document {
+        return Ok(());
+    }
+    for (scoped, rejected, value) in [
+        (page, true, "not available with html or markdown"),
+        (
+            requests,
+            url == "about:blank",
+            "needs a base_url with html or markdown",
+        ),
+    ] {
+        if let Some((name, _)) = scoped.iter().find(|(_, set)| *set && rejected) {
+            return Err(WorkerError::InvalidParam {
+                name: (*name).to_owned(),
+                value: value.to_owned(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Origin part of the cache key, documents without a `base_url` share one.
+pub fn origin(url: &Option<Url>, base_url: &Option<Url>) -> String {
+    match url.as_ref().or(base_url.as_ref()) {
+        Some(url) => url.origin().ascii_serialization(),
+        None => "document".to_owned(),
+    }
+}
+
+/// Replaces the document of the main frame with `html`.
+pub async fn load(page: &Page, html: &str) -> Result<(), WorkerError> {
+    page.goto("about:blank").await?;
+    let frame_id = page.mainframe().await?.ok_or(CdpError::NotFound)?;
+    page.execute(SetDocumentContentParams::new(frame_id, html))
+        .await?;
+    Ok(())
+}
+
+fn from_markdown(markdown: &str, stylesheet: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+    let mut body = String::new();
+    html::push_html(&mut body, Parser::new_ext(markdown, options));
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        stylesheet, body
+    )
+}
+
+/// Adds a `<base>` element as the first child of `<head>`, or right after the
+/// doctype if there's no head, so the document stays in standards mode.
+fn with_base(mut document: String, base_url: &Url) -> String {
+    let lowercase = document.to_ascii_lowercase();
+    let at = ["<head>", "<head ", "<!doctype"]
+        .iter()
+        .find_map(|tag| {
+            let start = lowercase.find(tag)?;
+            lowercase[start..].find('>').map(|end| start + end + 1)
+        })
+        .unwrap_or(0);
+    let base = format!(
+        "<base href=\"{}\">",
+        base_url
+            .as_str()
+            .replace('&', "&amp;")
+            .replace('"', "&quot;")
+    );
+    document.insert_str(at, &base);
+    document
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn based(document: &str) -> String {
+        with_base(
+            document.to_owned(),
+            &Url::parse("https://example.com/docs/").unwrap(),
+        )
+    }
+
+    #[test]
+    fn puts_base_first_in_head() {
+        assert_eq!(
+            based("<!DOCTYPE html><html><head><title>t</title></head></html>"),
+            "<!DOCTYPE html><html><head><base href=\"https://example.com/docs/\"><title>t</title></head></html>"
+        );
+        assert_eq!(
+            based("<HTML><Head lang=\"en\"><meta charset=\"utf-8\">"),
+            "<HTML><Head lang=\"en\"><base href=\"https://example.com/docs/\"><meta charset=\"utf-8\">"
+        );
+    }
+
+    #[test]
+    fn puts_base_after_doctype_without_head() {
+        assert_eq!(
+            based("<!doctype html>\n<header>é</header>"),
+            "<!doctype html><base href=\"https://example.com/docs/\">\n<header>é</header>"
+        );
+        assert_eq!(
+            based("<p>fragment</p>"),
+            "<base href=\"https://example.com/docs/\"><p>fragment</p>"
+        );
+        assert_eq!(based(""), "<base href=\"https://example.com/docs/\">");
+    }
+
+    #[test]
+    fn escapes_base_href() {
+        assert_eq!(
+            with_base(
+                "<head>".to_owned(),
+                &Url::parse("https://example.com/?a=1&b=\"2\"").unwrap()
+            ),
+            "<head><base href=\"https://example.com/?a=1&amp;b=%222%22\">"
+        );
+    }
+
+    #[test]
+    fn checks_origin_scoped_params() {
+        let rejected = |result: Result<(), WorkerError>| match result {
+            Err(WorkerError::InvalidParam { name, .. }) => Some(name),
+            _ => None,
+        };
+        let page = [("geolocation", false), ("cookies", true)];
+        let requests = [("headers", true)];
+        let base = "https://example.com/";
+
+        assert_eq!(rejected(check_scoped(base, false, &page, &requests)), None);
+        assert_eq!(
+            rejected(check_scoped(base, true, &page, &requests)).as_deref(),
+            Some("cookies")
+        );
+        assert_eq!(
+            rejected(check_scoped("about:blank", true, &[], &requests)).as_deref(),
+            Some("headers")
+        );
+        assert_eq!(rejected(check_scoped(base, true, &[], &requests)), None);
+        assert_eq!(
+            rejected(check_scoped("about:blank", true, &page[..1], &[])),
+            None
+        );
+    }
+}
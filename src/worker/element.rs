@@ -0,0 +1,69 @@
+use chromiumoxide::Page;
+use chromiumoxide_cdp::cdp::browser_protocol::page::Viewport;
+
+use crate::error::WorkerError;
+
+/// Looks the selector up in the document and then in every same-origin
+/// (i)frame, scrolls the match into view and returns its border box in
+/// page coordinates of the top-level document.
+const ELEMENT_BOX_JS: &str = r#"
+((selector) => {
+    const find = (doc, frames) => {
+        const element = doc.querySelector(selector);
+        if (element) return { element, frames };
+        for (const frame of doc.querySelectorAll('iframe, frame')) {
+            let child = null;
+            try { child = frame.contentDocument; } catch (e) {}
+            const found = child && find(child, frames.concat([frame]));
+            if (found) return found;
+        }
+        return null;
+    };
+    const found = find(document, []);
+    if (!found) return null;
+    found.element.scrollIntoView({ block: 'center', inline: 'center' });
+    let { x, y, width, height } = found.element.getBoundingClientRect();
+    for (const frame of found.frames) {
+        const rect = frame.getBoundingClientRect();
+        x += rect.left + frame.clientLeft;
+        y += rect.top + frame.clientTop;
+    }
+    return { x: x + window.scrollX, y: y + window.scrollY, width, height };
+})
+"#;
+
+#[derive(Debug, Deserialize)]
+pub struct ElementBox {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl ElementBox {
+    /// Grows the box by `padding` css pixels on every side, without going past the page origin.
+    pub fn to_clip(&self, padding: f64, scale: f64) -> Viewport {
+        let x = (self.x - padding).max(0.0);
+        let y = (self.y - padding).max(0.0);
+        Viewport {
+            x,
+            y,
+            width: self.x + self.width + padding - x,
+            height: self.y + self.height + padding - y,
+            scale,
+        }
+    }
+}
+
+pub async fn element_box(page: &Page, selector: &str) -> Result<ElementBox, WorkerError> {
+    page.evaluate_expression(format!(
+        "{}({})",
+        ELEMENT_BOX_JS,
+        serde_json::to_string(selector).unwrap()
+    ))
+    .await?
+    .into_value::<ElementBox>()
+    .ok()
+    .filter(|element| element.width > 0.0 && element.height > 0.0)
+    .ok_or_else(|| WorkerError::SelectorNotFound(selector.to_owned()))
+}
@@ -0,0 +1,573 @@
+use std::hash::{Hash, Hasher};
+
+use chromiumoxide::Page;
+use chromiumoxide_cdp::cdp::browser_protocol::browser::{
+    BrowserContextId, PermissionDescriptor, PermissionSetting, SetPermissionParams,
+};
+use chromiumoxide_cdp::cdp::browser_protocol::emulation::{
+    ClearDeviceMetricsOverrideParams, ClearGeolocationOverrideParams, MediaFeature,
+    SetDeviceMetricsOverrideParams, SetEmulatedMediaParams, SetGeolocationOverrideParams,
+    SetLocaleOverrideParams, SetTimezoneOverrideParams, SetTouchEmulationEnabledParams,
+};
+use chromiumoxide_cdp::cdp::browser_protocol::network::SetUserAgentOverrideParams;
+
+use crate::config::SERVER_CONFIG;
+use crate::error::WorkerError;
+use crate::util::param::string_param;
+use crate::worker::transform::MAX_RESIZE;
+
+const IPHONE_USER_AGENT: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.0 Mobile/15E148 Safari/604.1";
+const IPAD_USER_AGENT: &str = "Mozilla/5.0 (iPad; CPU OS 17_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.0 Mobile/15E148 Safari/604.1";
+const PIXEL_USER_AGENT: &str = "Mozilla/5.0 (Linux; Android 14; Pixel 7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Mobile Safari/537.36";
+
+/// Viewport, pixel ratio, input and user agent of an emulated device.
+///
+/// Buckets can add their own presets under `devices` in `config.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Device {
+    pub width: u32,
+    pub height: u32,
+    #[serde(default = "default_device_scale_factor")]
+    pub device_scale_factor: f64,
+    #[serde(default)]
+    pub mobile: bool,
+    #[serde(default)]
+    pub touch: bool,
+    #[serde(default)]
+    pub user_agent: Option<String>,
+}
+
+/// Device params set by the request itself, they win over the preset.
+#[derive(Debug, Default)]
+pub struct DeviceOverrides {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub device_scale_factor: Option<f64>,
+    pub mobile: Option<bool>,
+    pub user_agent: Option<String>,
+}
+
+impl Device {
+    /// The request `overrides` on top of the `preset`, if any, on top of
+    /// `fallback`, the bucket defaults. Touch only comes with a preset.
+    pub fn resolve(
+        bucket: &str,
+        preset: Option<&str>,
+        overrides: DeviceOverrides,
+        fallback: Device,
+    ) -> Result<Device, WorkerError> {
+        let preset = match preset {
+            Some(name) => Some(Device::lookup(bucket, name)?),
+            None => None,
+        };
+        Ok(Device {
+            width: overrides
+                .width
+                .or(preset.as_ref().map(|preset| preset.width))
+                .unwrap_or(fallback.width),
+            height: overrides
+                .height
+                .or(preset.as_ref().map(|preset| preset.height))
+                .unwrap_or(fallback.height),
+            device_scale_factor: overrides
+                .device_scale_factor
+                .or(preset.as_ref().map(|preset| preset.device_scale_factor))
+                .unwrap_or(fallback.device_scale_factor),
+            mobile: overrides
+                .mobile
+                .or(preset.as_ref().map(|preset| preset.mobile))
+                .unwrap_or(fallback.mobile),
+            touch: preset
+                .as_ref()
+                .map_or(fallback.touch, |preset| preset.touch),
+            user_agent: overrides
+                .user_agent
+                .or_else(|| preset.and_then(|preset| preset.user_agent))
+                .or(fallback.user_agent),
+        })
+    }
+
+    /// Looks `name` up in the bucket presets first, then in the built-in ones.
+    pub fn lookup(bucket: &str, name: &str) -> Result<Device, WorkerError> {
+        SERVER_CONFIG
+            .buckets
+            .get(bucket)
+            .and_then(|config| config.devices.get(name).cloned())
+            .or_else(|| builtin_device(name))
+            .ok_or_else(|| WorkerError::InvalidParam {
+                name: "device".to_owned(),
+                value: name.to_owned(),
+            })
+    }
+
+    pub async fn emulate(&self, page: &Page) -> Result<(), WorkerError> {
+        page.execute(SetDeviceMetricsOverrideParams::new(
+            self.width as i64,
+            self.height as i64,
+            self.device_scale_factor,
+            self.mobile,
+        ))
+        .await?;
+        if self.touch {
+            page.execute(SetTouchEmulationEnabledParams {
+                enabled: true,
+                max_touch_points: Some(5),
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Switches a loaded page over to this device and waits until the
+    /// relayout has been painted.
+    pub async fn reemulate(&self, page: &Page) -> Result<(), WorkerError> {
+        if !self.touch {
+            page.execute(SetTouchEmulationEnabledParams::new(false))
+                .await?;
+        }
+        self.emulate(page).await?;
+        page.evaluate_expression(
+            "new Promise((resolve) => requestAnimationFrame(() => requestAnimationFrame(resolve)))",
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+string_param! {
+    /// One entry of `viewports`, a device preset name or `<width>x<height>[@<dpr>]`
+    /// with sides up to `MAX_RESIZE`. The string labels the capture too.
+    pub struct ViewportSpec, "viewports", |s| !s.trim().is_empty()
+}
+
+impl ViewportSpec {
+    fn parse(s: &str) -> Option<(u32, u32, f64)> {
+        let (size, device_scale_factor) = match s.split_once('@') {
+            Some((size, dpr)) => (size, dpr.parse::<f64>().ok()?),
+            None => (s, 1.0),
+        };
+        let (width, height) = size.split_once('x')?;
+        let (width, height) = (width.parse::<u32>().ok()?, height.parse::<u32>().ok()?);
+        let sides = 1..=MAX_RESIZE;
+        match sides.contains(&width)
+            && sides.contains(&height)
+            && (MIN_DPR..=MAX_DPR).contains(&device_scale_factor)
+        {
+            true => Some((width, height, device_scale_factor)),
+            false => None,
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        &self.0
+    }
+
+    /// The sized device, or the preset looked up like `device`.
+    pub fn device(&self, bucket: &str) -> Result<Device, WorkerError> {
+        match Self::parse(&self.0) {
+            Some((width, height, device_scale_factor)) => Ok(Device {
+                width,
+                height,
+                device_scale_factor,
+                mobile: false,
+                touch: false,
+                user_agent: None,
+            }),
+            None => Device::lookup(bucket, &self.0).map_err(|_| WorkerError::InvalidParam {
+                name: "viewports".to_owned(),
+                value: self.0.clone(),
+            }),
+        }
+    }
+}
+
+fn default_device_scale_factor() -> f64 {
+    1.0
+}
+
+/// Lowest `dpr` a request or bucket may set.
+pub const MIN_DPR: f64 = 0.5;
+
+/// Highest `dpr` a request or bucket may set.
+pub const MAX_DPR: f64 = 4.0;
+
+/// Device pixel ratio between `MIN_DPR` and `MAX_DPR`, like `2` or `1.5`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "f64", into = "f64")]
+pub struct Dpr(f64);
+
+impl Dpr {
+    pub fn ratio(self) -> f64 {
+        self.0
+    }
+}
+
+impl TryFrom<f64> for Dpr {
+    type Error = WorkerError;
+
+    fn try_from(value: f64) -> Result<Self, Self::Error> {
+        match (MIN_DPR..=MAX_DPR).contains(&value) {
+            true => Ok(Dpr(value)),
+            false => Err(WorkerError::InvalidParam {
+                name: "dpr".to_owned(),
+                value: value.to_string(),
+            }),
+        }
+    }
+}
+
+impl From<Dpr> for f64 {
+    fn from(value: Dpr) -> Self {
+        value.0
+    }
+}
+
+/// Hashed by its bits, NaN never gets in.
+impl Hash for Dpr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+fn builtin_device(name: &str) -> Option<Device> {
+    let (width, height, device_scale_factor, mobile, user_agent) = match name {
+        "iphone-se" => (375, 667, 2.0, true, Some(IPHONE_USER_AGENT)),
+        "iphone-15" | "iphone" => (393, 852, 3.0, true, Some(IPHONE_USER_AGENT)),
+        "iphone-15-pro-max" => (430, 932, 3.0, true, Some(IPHONE_USER_AGENT)),
+        "pixel-7" | "pixel" => (412, 915, 2.625, true, Some(PIXEL_USER_AGENT)),
+        "ipad" => (810, 1080, 2.0, true, Some(IPAD_USER_AGENT)),
+        "ipad-pro" => (1024, 1366, 2.0, true, Some(IPAD_USER_AGENT)),
+        "laptop" => (1366, 768, 1.0, false, None),
+        "desktop-hd" | "desktop" => (1920, 1080, 1.0, false, None),
+        "desktop-retina" => (1440, 900, 2.0, false, None),
+        "desktop-4k" => (3840, 2160, 1.0, false, None),
+        _ => return None,
+    };
+    Some(Device {
+        width,
+        height,
+        device_scale_factor,
+        mobile,
+        touch: mobile,
+        user_agent: user_agent.map(|user_agent| user_agent.to_owned()),
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorScheme {
+    Light,
+    Dark,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaType {
+    Screen,
+    Print,
+}
+
+/// CSS media type and media features, see `Emulation.setEmulatedMedia`.
+#[derive(Debug, Clone, Default)]
+pub struct Media {
+    pub color_scheme: Option<ColorScheme>,
+    pub media_type: Option<MediaType>,
+    pub reduced_motion: Option<bool>,
+}
+
+impl Media {
+    pub async fn emulate(&self, page: &Page) -> Result<(), WorkerError> {
+        let mut features = vec![];
+        if let Some(color_scheme) = self.color_scheme {
+            features.push(MediaFeature::new(
+                "prefers-color-scheme",
+                match color_scheme {
+                    ColorScheme::Light => "light",
+                    ColorScheme::Dark => "dark",
+                },
+            ));
+        }
+        if let Some(reduced_motion) = self.reduced_motion {
+            features.push(MediaFeature::new(
+                "prefers-reduced-motion",
+                match reduced_motion {
+                    true => "reduce",
+                    false => "no-preference",
+                },
+            ));
+        }
+        if features.is_empty() && self.media_type.is_none() {
+            return Ok(());
+        }
+
+        page.execute(SetEmulatedMediaParams {
+            media: self.media_type.map(|media_type| {
+                match media_type {
+                    MediaType::Screen => "screen",
+                    MediaType::Print => "print",
+                }
+                .to_owned()
+            }),
+            features: Some(features),
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+string_param! {
+    /// Latitude, longitude and optional accuracy in meters, as `lat,lon[,accuracy]`.
+    pub struct Geolocation, "geolocation", |s| Geolocation::parse(s).is_some()
+}
+
+impl Geolocation {
+    fn parse(s: &str) -> Option<(f64, f64, f64)> {
+        let mut parts = s.split(',').map(|part| part.trim().parse::<f64>());
+        let latitude = parts.next()?.ok()?;
+        let longitude = parts.next()?.ok()?;
+        let accuracy = match parts.next() {
+            Some(accuracy) => accuracy.ok()?,
+            None => 100.0,
+        };
+        if parts.next().is_some()
+            || !(-90.0..=90.0).contains(&latitude)
+            || !(-180.0..=180.0).contains(&longitude)
+            || accuracy.is_nan()
+            || accuracy < 0.0
+        {
+            return None;
+        }
+        Some((latitude, longitude, accuracy))
+    }
+
+    /// (latitude, longitude, accuracy)
+    pub fn coordinates(&self) -> (f64, f64, f64) {
+        Self::parse(&self.0).unwrap()
+    }
+}
+
+/// Timezone, locale and position the page sees.
+#[derive(Debug, Clone, Default)]
+pub struct Locale {
+    /// IANA id, e.g. `Europe/Berlin`
+    pub timezone: Option<String>,
+    /// BCP 47 tag used by `Intl`, e.g. `de-DE`
+    pub locale: Option<String>,
+    /// `Accept-Language` header and `navigator.languages`, defaults to `locale`
+    pub accept_language: Option<String>,
+    pub geolocation: Option<Geolocation>,
+}
+
+impl Locale {
+    pub fn accept_language(&self) -> Option<&str> {
+        self.accept_language.as_deref().or(self.locale.as_deref())
+    }
+
+    /// `origin` is granted the geolocation permission in `context`, the browser
+    /// context of `page`, see `Locale::revoke`. Opaque origins are skipped,
+    /// nothing can be granted to them.
+    pub async fn emulate(
+        &self,
+        page: &Page,
+        context: &BrowserContextId,
+        origin: &str,
+    ) -> Result<(), WorkerError> {
+        if let Some(timezone) = &self.timezone {
+            page.emulate_timezone(SetTimezoneOverrideParams::new(timezone))
+                .await?;
+        }
+        if let Some(locale) = &self.locale {
+            page.emulate_locale(SetLocaleOverrideParams {
+                locale: Some(locale.clone()),
+            })
+            .await?;
+        }
+        if let Some(geolocation) = self.geolocation.as_ref().filter(|_| origin != "null") {
+            let (latitude, longitude, accuracy) = geolocation.coordinates();
+            page.execute(SetGeolocationOverrideParams {
+                latitude: Some(latitude),
+                longitude: Some(longitude),
+                accuracy: Some(accuracy),
+            })
+            .await?;
+            page.execute(SetPermissionParams {
+                permission: PermissionDescriptor::new("geolocation"),
+                setting: PermissionSetting::Granted,
+                origin: Some(origin.to_owned()),
+                browser_context_id: Some(context.clone()),
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Takes back the permission granted by `Locale::emulate`.
+    /// Permissions live in the browser context, `reset` can't see them.
+    pub async fn revoke(
+        &self,
+        page: &Page,
+        context: &BrowserContextId,
+        origin: &str,
+    ) -> Result<(), WorkerError> {
+        if self.geolocation.is_some() && origin != "null" {
+            page.execute(SetPermissionParams {
+                permission: PermissionDescriptor::new("geolocation"),
+                setting: PermissionSetting::Prompt,
+                origin: Some(origin.to_owned()),
+                browser_context_id: Some(context.clone()),
+            })
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Overrides the user agent and/or `Accept-Language`, both go through the same command.
+pub async fn override_user_agent(
+    page: &Page,
+    user_agent: Option<&str>,
+    accept_language: Option<&str>,
+) -> Result<(), WorkerError> {
+    if user_agent.is_none() && accept_language.is_none() {
+        return Ok(());
+    }
+    let user_agent = match user_agent {
+        Some(user_agent) => user_agent.to_owned(),
+        None => page.user_agent().await?,
+    };
+    page.set_user_agent(SetUserAgentOverrideParams {
+        user_agent,
+        accept_language: accept_language.map(|accept_language| accept_language.to_owned()),
+        platform: None,
+        user_agent_metadata: None,
+    })
+    .await?;
+    Ok(())
+}
+
+/// Drops every per-task override, so the next task on this pooled page starts clean.
+pub async fn reset(page: &Page) -> Result<(), WorkerError> {
+    page.execute(ClearDeviceMetricsOverrideParams {}).await?;
+    // empty media and features disable the emulation
+    page.execute(SetEmulatedMediaParams {
+        media: Some("".to_owned()),
+        features: Some(vec![]),
+    })
+    .await?;
+    page.execute(SetTouchEmulationEnabledParams::new(false))
+        .await?;
+    // an empty timezone id and a missing locale restore the host defaults
+    page.emulate_timezone(SetTimezoneOverrideParams::new(""))
+        .await?;
+    page.emulate_locale(SetLocaleOverrideParams { locale: None })
+        .await?;
+    page.execute(ClearGeolocationOverrideParams {}).await?;
+    // an empty user agent removes the override
+    page.set_user_agent(SetUserAgentOverrideParams::new(""))
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn geolocation_accuracy_defaults_to_100m() {
+        let berlin = Geolocation::try_from("52.52,13.405".to_owned()).unwrap();
+        assert_eq!(berlin.coordinates(), (52.52, 13.405, 100.0));
+        let sydney = Geolocation::try_from(" -33.87 , 151.21 , 25 ".to_owned()).unwrap();
+        assert_eq!(sydney.coordinates(), (-33.87, 151.21, 25.0));
+    }
+
+    #[test]
+    fn geolocation_stays_on_the_globe() {
+        let valid = |s: &str| Geolocation::try_from(s.to_owned()).is_ok();
+        assert!(valid("90,180,0"));
+        assert!(valid("-90,-180"));
+        assert!(!valid("90.1,0"));
+        assert!(!valid("0,-180.5"));
+        assert!(!valid("0,0,-1"));
+    }
+
+    #[test]
+    fn geolocation_takes_two_or_three_numbers() {
+        for s in [
+            "",
+            "52.52",
+            "52.52,",
+            "north,east",
+            "NaN,0",
+            "0,0,NaN",
+            "0,0,10,1",
+        ] {
+            let err = Geolocation::try_from(s.to_owned()).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("invalid parameter \"geolocation\" {:?}", s)
+            );
+        }
+    }
+
+    #[test]
+    fn dpr_is_a_ratio() {
+        assert_eq!(Dpr::try_from(2.0).unwrap().ratio(), 2.0);
+        assert_eq!(Dpr::try_from(MIN_DPR).unwrap().ratio(), 0.5);
+        assert_eq!(Dpr::try_from(MAX_DPR).unwrap().ratio(), 4.0);
+        let accepted: Vec<f64> = [0.0, 0.2, 4.5, 20.0, -2.0, f64::NAN, f64::INFINITY]
+            .into_iter()
+            .filter(|&dpr| Dpr::try_from(dpr).is_ok())
+            .collect();
+        assert!(accepted.is_empty(), "accepted {:?}", accepted);
+    }
+
+    #[test]
+    fn sized_viewports() {
+        assert_eq!(ViewportSpec::parse("1280x720"), Some((1280, 720, 1.0)));
+        assert_eq!(ViewportSpec::parse("390x844@3"), Some((390, 844, 3.0)));
+        assert_eq!(ViewportSpec::parse("800x600@1.5"), Some((800, 600, 1.5)));
+        assert_eq!(
+            ViewportSpec::parse("8192x8192@0.5"),
+            Some((MAX_RESIZE, MAX_RESIZE, MIN_DPR))
+        );
+
+        let spec = ViewportSpec::try_from("390x844@3".to_owned()).unwrap();
+        assert_eq!(spec.label(), "390x844@3");
+        let device = spec.device("any").unwrap();
+        assert_eq!(
+            (device.width, device.height, device.device_scale_factor),
+            (390, 844, 3.0)
+        );
+        assert!(!device.mobile && !device.touch && device.user_agent.is_none());
+    }
+
+    #[test]
+    fn other_viewports_are_preset_names() {
+        let sized = [
+            "iphone",
+            "0x720",
+            "1280x0",
+            "1280x",
+            "x720",
+            "-1x720",
+            "1280*720",
+            "1280x720@",
+            "1280x720@0",
+            "1280x720@0.4",
+            "1280x720@4.5",
+            "8193x720",
+            "1280x100000",
+            "1280x720@2@2",
+        ]
+        .into_iter()
+        .find(|s| ViewportSpec::parse(s).is_some());
+        assert_eq!(sized, None);
+    }
+
+    #[test]
+    fn rejects_blank_viewports() {
+        assert!(ViewportSpec::try_from("".to_owned()).is_err());
+        assert!(ViewportSpec::try_from("  ".to_owned()).is_err());
+        assert!(ViewportSpec::try_from("iphone".to_owned()).is_ok());
+    }
+}
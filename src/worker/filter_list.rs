@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::OnceLock;
+
+use adblock::lists::{FilterSet, ParseOptions};
+use adblock::request::Request;
+use adblock::Engine;
+use chromiumoxide_cdp::cdp::browser_protocol::network::ResourceType;
+use tide::log::info;
+
+use crate::config::{DAL_OP_MAP, SERVER_CONFIG};
+
+static AD_FILTERS: OnceLock<HashMap<String, AdFilter>> = OnceLock::new();
+
+/// Matcher compiled from the EasyList/uBlock style lists of one bucket.
+pub struct AdFilter(Engine);
+
+impl AdFilter {
+    /// The filter of `bucket`, `None` if it has no `filter_lists` or `load` didn't run.
+    pub fn get(bucket: &str) -> Option<&'static AdFilter> {
+        AD_FILTERS.get().and_then(|filters| filters.get(bucket))
+    }
+
+    /// `source_url` is the page the request is made from, needed for
+    /// `$third-party` and `$domain=` options. `main_frame` is set for the
+    /// navigation of the page itself.
+    pub fn blocks(
+        &self,
+        resource_type: &ResourceType,
+        main_frame: bool,
+        url: &str,
+        source_url: &str,
+    ) -> bool {
+        Request::new(url, source_url, request_type(resource_type, main_frame))
+            .map(|request| self.0.check_network_request(&request).matched)
+            .unwrap_or(false)
+    }
+}
+
+impl fmt::Debug for AdFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AdFilter")
+    }
+}
+
+/// Request type names used by the filter syntax. Only the document of the
+/// main frame is a `document`, the ones of iframes are `subdocument`s.
+fn request_type(resource_type: &ResourceType, main_frame: bool) -> &'static str {
+    match resource_type {
+        ResourceType::Document if main_frame => "document",
+        ResourceType::Document => "sub_frame",
+        ResourceType::Stylesheet => "stylesheet",
+        ResourceType::Image => "image",
+        ResourceType::Media => "media",
+        ResourceType::Font => "font",
+        ResourceType::Script => "script",
+        ResourceType::Xhr | ResourceType::Fetch | ResourceType::EventSource => "xmlhttprequest",
+        ResourceType::WebSocket => "websocket",
+        ResourceType::Ping | ResourceType::CspViolationReport => "ping",
+        _ => "other",
+    }
+}
+
+/// Reads and compiles the `filter_lists` of every bucket. Entries starting
+/// with `file://` are read from the local disk, everything else from the
+/// bucket storage. Runs once at startup.
+pub async fn load() -> io::Result<()> {
+    let mut filters = HashMap::new();
+    for (bucket, config) in &SERVER_CONFIG.buckets {
+        if config.filter_lists.is_empty() {
+            continue;
+        }
+        let mut filter_set = FilterSet::new(false);
+        for path in &config.filter_lists {
+            let list = match path.strip_prefix("file://") {
+                Some(path) => tokio::fs::read(path).await?,
+                None => DAL_OP_MAP.get(bucket).unwrap().read(path).await?,
+            };
+            let metadata = filter_set
+                .add_filter_list(&String::from_utf8_lossy(&list), ParseOptions::default());
+            info!(
+                "bucket {:#} filter list {:#} {:?}",
+                bucket, path, metadata.title
+            );
+        }
+        filters.insert(
+            bucket.clone(),
+            AdFilter(Engine::from_filter_set(filter_set, true)),
+        );
+    }
+    let _ = AD_FILTERS.set(filters);
+    Ok(())
+}
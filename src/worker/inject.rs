@@ -0,0 +1,120 @@
+use chromiumoxide::error::CdpError;
+use chromiumoxide::Page;
+use chromiumoxide_cdp::cdp::browser_protocol::page::SetBypassCspParams;
+use chromiumoxide_cdp::cdp::js_protocol::runtime::ExceptionDetails;
+use opendal::Operator;
+
+use crate::error::WorkerError;
+
+/// Appends one `<style>` element, so injected rules win over the page's own.
+const ADD_STYLE_JS: &str = r#"
+((css) => {
+    const style = document.createElement('style');
+    style.setAttribute('data-web-shim', '');
+    style.textContent = css;
+    (document.head || document.documentElement).appendChild(style);
+})
+"#;
+
+/// Styles and scripts to run on the page between load and the end of `wait_until`.
+#[derive(Debug, Clone, Default)]
+pub struct Injection {
+    pub css: Vec<String>,
+    pub js: Vec<String>,
+    /// css selector list, matches get `display: none`
+    pub hide_selectors: Option<String>,
+}
+
+impl Injection {
+    /// Bucket files come first, inline code of the request replaces the inline code of the bucket.
+    pub async fn resolve(
+        op: &Operator,
+        css_files: &Option<Vec<String>>,
+        js_files: &Option<Vec<String>>,
+        css: Option<String>,
+        js: Option<String>,
+        hide_selectors: Option<String>,
+    ) -> Result<Self, WorkerError> {
+        let mut injection = Injection {
+            css: read_all(op, css_files).await?,
+            js: read_all(op, js_files).await?,
+            hide_selectors,
+        };
+        injection.css.extend(css);
+        injection.js.extend(js);
+        Ok(injection)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.css.is_empty() && self.js.is_empty() && self.hide_selectors.is_none()
+    }
+
+    /// Adds the styles, then runs every script in order and awaits returned promises.
+    /// Exceptions don't fail the task, they're collected to be reported with the result.
+    pub async fn apply(&self, page: &Page) -> Result<Vec<String>, WorkerError> {
+        let mut styles = self.css.clone();
+        if let Some(selectors) = &self.hide_selectors {
+            styles.push(format!("{} {{ display: none !important; }}", selectors));
+        }
+        if !styles.is_empty() {
+            add_style(page, &styles.join("\n")).await?;
+        }
+
+        let mut errors = vec![];
+        for (index, script) in self.js.iter().enumerate() {
+            match page.evaluate_expression(script.as_str()).await {
+                Ok(_) => {}
+                Err(CdpError::JavascriptException(details)) => {
+                    errors.push(format!("js[{}] {}", index, describe(&details)))
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(errors)
+    }
+}
+
+/// Has to run before navigation, a strict `style-src` would drop injected styles.
+pub async fn bypass_csp(page: &Page) -> Result<(), WorkerError> {
+    page.execute(SetBypassCspParams::new(true)).await?;
+    Ok(())
+}
+
+pub async fn add_style(page: &Page, css: &str) -> Result<(), WorkerError> {
+    page.evaluate_expression(format!(
+        "{}({})",
+        ADD_STYLE_JS,
+        serde_json::to_string(css).unwrap()
+    ))
+    .await?;
+    Ok(())
+}
+
+async fn read_all(op: &Operator, paths: &Option<Vec<String>>) -> Result<Vec<String>, WorkerError> {
+    let mut contents = vec![];
+    for path in paths.iter().flatten() {
+        contents.push(String::from_utf8_lossy(&op.read(path).await?).into_owned());
+    }
+    Ok(contents)
+}
+
+/// First line of the exception, e.g. `TypeError: x is not a function`, with its position.
+fn describe(details: &ExceptionDetails) -> String {
+    let message = details
+        .exception
+        .as_ref()
+        .and_then(|exception| exception.description.as_deref())
+        .and_then(|description| description.lines().next())
+        .unwrap_or(&details.text);
+    format!(
+        "{} at {}:{}",
+        message,
+        details.line_number + 1,
+        details.column_number + 1
+    )
+}
+
+pub async fn reset(page: &Page) -> Result<(), WorkerError> {
+    page.execute(SetBypassCspParams::new(false)).await?;
+    Ok(())
+}
@@ -1,2 +1,24 @@
 pub mod screenshot;
-pub mod pdf;
\ No newline at end of file
+pub mod pdf;
+pub mod element;
+pub mod emulation;
+pub mod paper;
+pub mod scroll;
+pub mod wait;
+pub mod network;
+pub mod block;
+pub mod filter_list;
+pub mod inject;
+pub mod consent;
+pub mod actions;
+pub mod stitch;
+pub mod transform;
+pub mod output;
+pub mod watermark;
+pub mod contact_sheet;
+pub mod screencast;
+pub mod animation;
+pub mod document;
+pub mod template;
+pub mod bundle;
+pub mod pool;
\ No newline at end of file
@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chromiumoxide::Page;
+use chromiumoxide_cdp::cdp::browser_protocol::fetch::{
+    ContinueRequestParams, DisableParams, EnableParams, EventRequestPaused, FailRequestParams,
+    HeaderEntry,
+};
+use chromiumoxide_cdp::cdp::browser_protocol::network::{
+    ClearBrowserCookiesParams, CookieParam, ErrorReason, ResourceType, SetCookiesParams,
+};
+use futures::StreamExt;
+use tokio::task::JoinHandle;
+use url::Url;
+
+use crate::error::WorkerError;
+use crate::worker::block::Blocking;
+
+/// Extra headers, cookies and credentials for the target site, and requests to block.
+///
+/// Headers and credentials are only attached to requests going to the
+/// origin of the target url, never to third parties embedded in the page.
+#[derive(Debug, Clone, Default)]
+pub struct RequestOverrides {
+    pub headers: BTreeMap<String, String>,
+    pub cookies: BTreeMap<String, String>,
+    /// `user:password`
+    pub basic_auth: Option<String>,
+    pub blocking: Blocking,
+}
+
+impl RequestOverrides {
+    fn origin_headers(&self) -> Vec<HeaderEntry> {
+        let mut headers: Vec<HeaderEntry> = self
+            .headers
+            .iter()
+            .map(|(name, value)| HeaderEntry {
+                name: name.clone(),
+                value: value.clone(),
+            })
+            .collect();
+        if let Some(basic_auth) = &self.basic_auth {
+            headers.push(HeaderEntry {
+                name: "Authorization".to_owned(),
+                value: format!("Basic {}", STANDARD.encode(basic_auth)),
+            });
+        }
+        headers
+    }
+
+    /// Sets the cookies for `url` and starts intercepting requests if
+    /// headers have to be added or requests blocked. Interception stops
+    /// when the returned handle is dropped.
+    ///
+    /// Documents are on `about:blank`, its opaque origin gets no cookies.
+    /// Without a `base_url` no request matches it for the headers either.
+    pub async fn apply(&self, page: &Page, url: &str) -> Result<Interception, WorkerError> {
+        let opaque = Url::parse(url).map_or(true, |url| !url.origin().is_tuple());
+        if !self.cookies.is_empty() && !opaque {
+            page.execute(SetCookiesParams::new(
+                self.cookies
+                    .iter()
+                    .map(|(name, value)| {
+                        let mut cookie = CookieParam::new(name.clone(), value.clone());
+                        cookie.url = Some(url.to_owned());
+                        cookie
+                    })
+                    .collect::<Vec<_>>(),
+            ))
+            .await?;
+        }
+
+        let headers = self.origin_headers();
+        if headers.is_empty() && self.blocking.is_empty() {
+            return Ok(Interception(None));
+        }
+
+        let origin = Url::parse(url)
+            .map_err(|_| WorkerError::InvalidParam {
+                name: "url".to_owned(),
+                value: url.to_owned(),
+            })?
+            .origin();
+        let mut paused = page.event_listener::<EventRequestPaused>().await?;
+        page.execute(EnableParams {
+            patterns: None,
+            handle_auth_requests: None,
+        })
+        .await?;
+
+        let main_frame = page.mainframe().await?;
+        let page = page.clone();
+        let blocking = self.blocking.clone();
+        let source_url = url.to_owned();
+        Ok(Interception(Some(tokio::task::spawn(async move {
+            while let Some(event) = paused.next().await {
+                let navigation = event.resource_type == ResourceType::Document
+                    && main_frame.as_ref() == Some(&event.frame_id);
+                if blocking.blocks(
+                    &event.resource_type,
+                    navigation,
+                    &event.request.url,
+                    &source_url,
+                ) {
+                    let _ = page
+                        .execute(FailRequestParams::new(
+                            event.request_id.clone(),
+                            ErrorReason::BlockedByClient,
+                        ))
+                        .await;
+                    continue;
+                }
+                let mut params = ContinueRequestParams::new(event.request_id.clone());
+                if Url::parse(&event.request.url)
+                    .map(|url| url.origin() == origin)
+                    .unwrap_or(false)
+                {
+                    if !headers.is_empty() {
+                        params.headers = Some(with_headers(&event, &headers));
+                    }
+                }
+                let _ = page.execute(params).await;
+            }
+        }))))
+    }
+}
+
+/// `Fetch.continueRequest` replaces all headers, so the original ones are carried over.
+fn with_headers(event: &EventRequestPaused, headers: &[HeaderEntry]) -> Vec<HeaderEntry> {
+    let mut merged: Vec<HeaderEntry> = event
+        .request
+        .headers
+        .inner()
+        .as_object()
+        .map(|original| {
+            original
+                .iter()
+                .filter(|(name, _)| {
+                    !headers
+                        .iter()
+                        .any(|header| header.name.eq_ignore_ascii_case(name))
+                })
+                .filter_map(|(name, value)| {
+                    value.as_str().map(|value| HeaderEntry {
+                        name: name.clone(),
+                        value: value.to_owned(),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    merged.extend_from_slice(headers);
+    merged
+}
+
+/// Running request interception of one task.
+pub struct Interception(Option<JoinHandle<()>>);
+
+impl Drop for Interception {
+    fn drop(&mut self) {
+        if let Some(handle) = &self.0 {
+            handle.abort();
+        }
+    }
+}
+
+/// Stops interception and drops the cookies of the last task. Each pooled
+/// page has a browser context of its own, so this never touches another worker.
+pub async fn reset(page: &Page) -> Result<(), WorkerError> {
+    page.execute(DisableParams {}).await?;
+    page.execute(ClearBrowserCookiesParams {}).await?;
+    Ok(())
+}
+
+/// Bucket defaults overlaid with the request values, the request wins.
+pub fn merged(
+    defaults: &Option<BTreeMap<String, String>>,
+    request: Option<BTreeMap<String, String>>,
+) -> BTreeMap<String, String> {
+    let mut merged = defaults.clone().unwrap_or_default();
+    merged.extend(request.unwrap_or_default());
+    merged
+}
@@ -0,0 +1,170 @@
+use opendal::Operator;
+use tide::{Body, Redirect, Response, StatusCode};
+
+use crate::error::WorkerError;
+use crate::util::signature_v4::signed_url;
+use crate::worker::transform::Rendered;
+
+/// What a worker sends back to the request handler.
+#[derive(Debug)]
+pub struct TaskOutput {
+    pub signed_url: String,
+    /// exceptions thrown by injected scripts
+    pub script_errors: Vec<String>,
+    /// the capture was cut off at the bucket `max_output_height` or the
+    /// viewport width
+    pub truncated: bool,
+    /// several files from one capture, see `output_sizes` and `viewports`,
+    /// `signed_url` is their manifest then
+    pub variants: Option<Variants>,
+}
+
+/// One stored file of a multi-file capture.
+#[derive(Debug, Clone, Serialize)]
+pub struct Variant {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub bytes: usize,
+}
+
+/// Files of a multi-file capture, answered as JSON instead of a redirect.
+#[derive(Debug, Clone, Serialize)]
+pub struct Variants {
+    pub variants: Vec<Variant>,
+    /// all `viewports` side by side
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub composite: Option<Variant>,
+}
+
+impl Variants {
+    pub fn into_response(&self) -> Response {
+        let mut response = Response::new(StatusCode::Ok);
+        response.set_body(Body::from_json(self).unwrap());
+        response
+    }
+}
+
+/// `Variant` as kept in the manifest, urls are signed when answering.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredVariant {
+    pub path: String,
+    #[serde(default)]
+    pub label: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub bytes: usize,
+}
+
+impl StoredVariant {
+    pub async fn write(
+        op: &Operator,
+        path: String,
+        label: Option<String>,
+        rendered: Rendered,
+    ) -> Result<Self, WorkerError> {
+        let bytes = rendered.buf.len();
+        op.write(&path, rendered.buf).await?;
+        Ok(StoredVariant {
+            path,
+            label,
+            width: rendered.width,
+            height: rendered.height,
+            bytes,
+        })
+    }
+
+    async fn sign(self, op: &Operator, bucket: &str) -> Variant {
+        Variant {
+            url: signed_url(op, &self.path, bucket).await.unwrap(),
+            label: self.label,
+            width: self.width,
+            height: self.height,
+            bytes: self.bytes,
+        }
+    }
+}
+
+/// Stored next to the files of a multi-file capture, so a cached
+/// response can be answered without capturing again.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub variants: Vec<StoredVariant>,
+    #[serde(default)]
+    pub composite: Option<StoredVariant>,
+}
+
+impl Manifest {
+    /// Writes the manifest to `path` and signs the files.
+    pub async fn store(
+        self,
+        op: &Operator,
+        bucket: &str,
+        path: &str,
+    ) -> Result<Variants, WorkerError> {
+        op.write(path, serde_json::to_vec(&self).unwrap()).await?;
+        Ok(self.sign(op, bucket).await)
+    }
+
+    /// Files of a cached capture, `None` if the manifest at `path` can't be
+    /// read, the capture is redone then.
+    pub async fn cached(op: &Operator, bucket: &str, path: &str) -> Option<Variants> {
+        let manifest: Manifest = serde_json::from_slice(&op.read(path).await.ok()?).ok()?;
+        Some(manifest.sign(op, bucket).await)
+    }
+
+    async fn sign(self, op: &Operator, bucket: &str) -> Variants {
+        let mut variants = Vec::with_capacity(self.variants.len());
+        for variant in self.variants {
+            variants.push(variant.sign(op, bucket).await);
+        }
+        let composite = match self.composite {
+            Some(composite) => Some(composite.sign(op, bucket).await),
+            None => None,
+        };
+        Variants {
+            variants,
+            composite,
+        }
+    }
+}
+
+impl TaskOutput {
+    /// Redirects to the stored file, or lists the variants as JSON. Script
+    /// errors go along as a JSON array in `X-Script-Errors`, truncation as
+    /// `X-Truncated: true`. Neither is stored, so only the request that
+    /// rendered the file gets them, not the ones answered from the cache.
+    pub fn into_response(self) -> Response {
+        let mut response: Response = match &self.variants {
+            Some(variants) => variants.into_response(),
+            None => Redirect::new(&self.signed_url).into(),
+        };
+        if !self.script_errors.is_empty() {
+            response.insert_header(
+                "X-Script-Errors",
+                ascii_escaped(&serde_json::to_string(&self.script_errors).unwrap()),
+            );
+        }
+        if self.truncated {
+            response.insert_header("X-Truncated", "true");
+        }
+        response
+    }
+}
+
+/// Header values have to be ascii, anything else is `\u` escaped the way JSON does it.
+fn ascii_escaped(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c.is_ascii() {
+            escaped.push(c);
+        } else {
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                escaped.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+    escaped
+}
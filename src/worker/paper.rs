@@ -0,0 +1,171 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::WorkerError;
+use crate::util::param::string_param;
+
+/// Named paper sizes, dimensions in inches as used by `Page.printToPDF`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum PaperFormat {
+    Letter,
+    Legal,
+    Tabloid,
+    Ledger,
+    A3,
+    A4,
+    A5,
+    A6,
+}
+
+impl PaperFormat {
+    /// (width, height) in inches
+    pub fn size(&self) -> (f64, f64) {
+        match self {
+            PaperFormat::Letter => (8.5, 11.0),
+            PaperFormat::Legal => (8.5, 14.0),
+            PaperFormat::Tabloid => (11.0, 17.0),
+            PaperFormat::Ledger => (17.0, 11.0),
+            PaperFormat::A3 => (11.7, 16.54),
+            PaperFormat::A4 => (8.27, 11.7),
+            PaperFormat::A5 => (5.83, 8.27),
+            PaperFormat::A6 => (4.13, 5.83),
+        }
+    }
+}
+
+impl FromStr for PaperFormat {
+    type Err = WorkerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "letter" => Ok(PaperFormat::Letter),
+            "legal" => Ok(PaperFormat::Legal),
+            "tabloid" => Ok(PaperFormat::Tabloid),
+            "ledger" => Ok(PaperFormat::Ledger),
+            "a3" => Ok(PaperFormat::A3),
+            "a4" => Ok(PaperFormat::A4),
+            "a5" => Ok(PaperFormat::A5),
+            "a6" => Ok(PaperFormat::A6),
+            _ => Err(WorkerError::InvalidParam {
+                name: "paper".to_owned(),
+                value: s.to_owned(),
+            }),
+        }
+    }
+}
+
+impl TryFrom<String> for PaperFormat {
+    type Error = WorkerError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<PaperFormat> for String {
+    fn from(value: PaperFormat) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for PaperFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PaperFormat::Letter => "letter",
+            PaperFormat::Legal => "legal",
+            PaperFormat::Tabloid => "tabloid",
+            PaperFormat::Ledger => "ledger",
+            PaperFormat::A3 => "a3",
+            PaperFormat::A4 => "a4",
+            PaperFormat::A5 => "a5",
+            PaperFormat::A6 => "a6",
+        })
+    }
+}
+
+string_param! {
+    /// A CSS-like length such as `1.5cm`, `10mm`, `96px` or `0.4in`.
+    /// A bare number is taken as inches.
+    pub struct Length, "length", |s| Length::parse_inches(s).is_some()
+}
+
+impl Length {
+    fn parse_inches(s: &str) -> Option<f64> {
+        let s = s.trim();
+        let (value, unit) =
+            s.split_at(s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len()));
+        let value: f64 = value.trim().parse().ok()?;
+        let per_inch = match unit {
+            "" | "in" => 1.0,
+            "cm" => 2.54,
+            "mm" => 25.4,
+            "px" => 96.0,
+            _ => return None,
+        };
+        Some(value / per_inch).filter(|inches| inches.is_finite() && *inches >= 0.0)
+    }
+
+    pub fn inches(&self) -> f64 {
+        Self::parse_inches(&self.0).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paper_formats_ignore_case() {
+        assert_eq!("a4".parse::<PaperFormat>().ok(), Some(PaperFormat::A4));
+        assert_eq!("A4".parse::<PaperFormat>().ok(), Some(PaperFormat::A4));
+        assert_eq!(
+            "Letter".parse::<PaperFormat>().ok(),
+            Some(PaperFormat::Letter)
+        );
+        assert!("".parse::<PaperFormat>().is_err());
+        assert!("a7".parse::<PaperFormat>().is_err());
+        assert!("a4 ".parse::<PaperFormat>().is_err());
+        assert!("letter-size".parse::<PaperFormat>().is_err());
+        assert_eq!(PaperFormat::A3.to_string(), "a3");
+    }
+
+    #[test]
+    fn ledger_is_tabloid_on_its_side() {
+        let (width, height) = PaperFormat::Tabloid.size();
+        assert_eq!(PaperFormat::Ledger.size(), (height, width));
+        assert_eq!(PaperFormat::Tabloid.size(), (11.0, 17.0));
+    }
+
+    #[test]
+    fn lengths_convert_to_inches() {
+        for s in ["1in", "2.54cm", "25.4mm", "96px"] {
+            let inches = Length::try_from(s.to_owned()).unwrap().inches();
+            assert!((inches - 1.0).abs() < 1e-9, "{} is {}in", s, inches);
+        }
+        // bare numbers are inches, blanks around the number and unit are fine
+        assert_eq!(Length::try_from("0.5".to_owned()).unwrap().inches(), 0.5);
+        assert_eq!(
+            Length::try_from(" 1.5 in ".to_owned()).unwrap().inches(),
+            1.5
+        );
+        assert_eq!(Length::try_from("0".to_owned()).unwrap().inches(), 0.0);
+    }
+
+    #[test]
+    fn rejects_malformed_lengths() {
+        let accepted: Vec<&str> = [
+            "", "in", "1pt", "1CM", "-1in", "1e3in", "inf", "NaN", "1..5mm",
+        ]
+        .into_iter()
+        .filter(|s| Length::try_from(s.to_string()).is_ok())
+        .collect();
+        assert!(accepted.is_empty(), "accepted {:?}", accepted);
+    }
+
+    #[test]
+    fn keeps_the_length_as_given() {
+        let length = Length::try_from("10mm".to_owned()).unwrap();
+        assert_eq!(String::from(length), "10mm");
+    }
+}
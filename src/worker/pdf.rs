@@ -1,40 +1,30 @@
-use async_std::task::sleep;
 use chromiumoxide::{Page};
 
 
-use futures::lock::Mutex;
 use lazy_static::lazy_static;
 
 
 
-use tide::{Error, Redirect, Request, StatusCode};
+use tide::{http::Method, Error, Redirect, Request};
 
 
-use std::time::{Duration};
-
+use chromiumoxide_cdp::cdp::browser_protocol::browser::BrowserContextId;
+use chromiumoxide_cdp::cdp::browser_protocol::dom::Rgba;
+use chromiumoxide_cdp::cdp::browser_protocol::emulation::SetDefaultBackgroundColorOverrideParams;
 use chromiumoxide_cdp::cdp::browser_protocol::page::{
     PrintToPdfParams, NavigateParams,
 };
-use futures::channel::mpsc::{unbounded, Sender, UnboundedReceiver, UnboundedSender};
+use futures::channel::mpsc::Sender;
 use futures::channel::oneshot::{channel as oneshot_channel, Sender as OneshotSender};
-use futures::StreamExt;
 
 use serde::{Deserialize, Serialize};
 
-use tide::log::{debug, info};
-
-use chrono::{ TimeDelta, offset::Local};
+use tide::log::debug;
 
 use url::Url;
 
 lazy_static! {
-    static ref PDF_TASK_CHANNEL: (
-        UnboundedSender<PDFTask>,
-        Mutex<UnboundedReceiver<PDFTask>>
-    ) = {
-        let (tx, rx) = unbounded();
-        (tx, Mutex::new(rx))
-    };
+    static ref PDF_TASKS: TaskQueue<PDFTask> = TaskQueue::new();
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -48,21 +38,19 @@ pub struct Claims {
 pub struct PDFWorker {}
 
 impl PDFWorker {
-    pub async fn new(id: usize, page: Page, ptx: Sender<usize>) {
+    pub async fn new(id: usize, page: Page, context: BrowserContextId, ptx: Sender<usize>) {
         debug!("worker {:#} create {:?}", id, page);
         tokio::task::spawn(async move {
             debug!("worker {:#} start", id);
             loop {
                 if let Some(PDFTask(tx, inner, navigate_params, cdp_params)) =
-                    PDF_TASK_CHANNEL.1.lock().await.next().await
+                    PDF_TASKS.next().await
                 {
-                    match worker(id, &page, inner, navigate_params, cdp_params).await {
-                        Ok(uri) => {
-                            tx.send(Some(uri));
-                        }
-                        Err(_) => {
-                            tx.send(None);
-                        }
+                    // virtual time can't be turned back to real time, so the page is not reused
+                    let recycle = inner.virtual_time_budget.is_some();
+                    let _ = tx.send(worker(id, &page, &context, inner, navigate_params, cdp_params).await);
+                    if recycle {
+                        break;
                     }
                 }
             }
@@ -77,11 +65,36 @@ impl PDFWorker {
 pub async fn worker(
     id: usize,
     page: &Page,
+    context: &BrowserContextId,
     inner: PDFTaskInner,
     navigate_params: NavigateParams,
     cdp_params: PrintToPdfParams,
-) -> Result<String, ()> {
+) -> Result<TaskOutput, WorkerError> {
     debug!("worker {:#} recv {:#} {:?}", id, inner.filename, cdp_params);
+    let origin = Url::parse(&navigate_params.url)
+        .map(|url| url.origin().ascii_serialization())
+        .unwrap_or_default();
+    let locale = inner.locale.clone();
+    let result = print(id, page, context, inner, &origin, navigate_params, cdp_params).await;
+
+    let _ = page.goto("about:blank").await;
+    let _ = emulation::reset(page).await;
+    let _ = locale.revoke(page, context, &origin).await;
+    let _ = network::reset(page).await;
+    let _ = inject::reset(page).await;
+
+    result
+}
+
+async fn print(
+    id: usize,
+    page: &Page,
+    context: &BrowserContextId,
+    inner: PDFTaskInner,
+    origin: &str,
+    navigate_params: NavigateParams,
+    cdp_params: PrintToPdfParams,
+) -> Result<TaskOutput, WorkerError> {
     let op = DAL_OP_MAP.get(&inner.bucket).unwrap();
     let filename = format!(
         "{:#}.{:#}",
@@ -90,34 +103,69 @@ pub async fn worker(
     )
     .to_owned();
 
-    let _ = page.goto(navigate_params).await.unwrap();
-
-    sleep(Duration::from_secs(10)).await;
-
-    let img_buf = page
-        .pdf(PrintToPdfParams {
-            landscape: None,
-            display_header_footer: None,
-            print_background: None,
-            scale: Some(1.0),
-            paper_width: None,
-            paper_height: None,
-            margin_top: None,
-            margin_bottom: None,
-            margin_left: None,
-            margin_right: None,
-            page_ranges: None,
-            header_template: None,
-            footer_template: None,
-            prefer_css_page_size: None,
-            transfer_mode: None,
+    inner.media.emulate(page).await?;
+    inner.locale.emulate(page, context, origin).await?;
+    emulation::override_user_agent(page, None, inner.locale.accept_language()).await?;
+    let _interception = inner.overrides.apply(page, &navigate_params.url).await?;
+    if !inner.injection.is_empty() || inner.cookie_banners.is_some() {
+        inject::bypass_csp(page).await?;
+    }
+
+    let deadline = Instant::now() + Duration::from_millis(inner.wait_timeout);
+    let (script_errors, virtual_time) = navigate(
+        page,
+        navigate_params,
+        inner.document.as_deref(),
+        &inner.wait_until,
+        inner.wait_timeout,
+        async {
+            // the budget shouldn't run down while the page is still loading
+            let virtual_time = match inner.virtual_time_budget {
+                Some(budget) => Some(VirtualTime::start(page, budget).await?),
+                None => None,
+            };
+            if let Some(cookie_banners) = &inner.cookie_banners {
+                cookie_banners.dismiss(page).await?;
+            }
+            Ok((inner.injection.apply(page).await?, virtual_time))
+        },
+    )
+    .await?;
+
+    if let Some(virtual_time) = virtual_time {
+        virtual_time.expired(deadline, inner.wait_timeout).await?;
+    }
+
+    if let Some(cookie_banners) = &inner.cookie_banners {
+        cookie_banners.accept(page).await?;
+    }
+
+    actions::run(page, &inner.actions).await?;
+
+    if inner.omit_background {
+        page.execute(SetDefaultBackgroundColorOverrideParams {
+            color: Some(Rgba {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: Some(0.),
+            }),
         })
-        .await
-        .unwrap();
+        .await?;
+    }
+
+    let img_buf = page.pdf(cdp_params).await;
+
+    if inner.omit_background {
+        page.execute(SetDefaultBackgroundColorOverrideParams { color: None })
+            .await?;
+    }
+
+    let img_buf = img_buf?;
 
     let file_size = &img_buf.len();
 
-    op.write(&filename, img_buf).await;
+    op.write(&filename, img_buf).await?;
 
     let signed_url = signed_url(op, &filename, &inner.bucket).await.unwrap();
 
@@ -128,30 +176,88 @@ pub async fn worker(
         file_size,
     );
 
-    page.goto("about:blank").await.unwrap();
-
-    return Ok(signed_url);
+    return Ok(TaskOutput {
+        signed_url,
+        script_errors,
+        truncated: false,
+        variants: None,
+    });
 }
 
-pub async fn pdf(req: Request<()>, bucket: &str) -> tide::Result {
-    let params: PDFRequestQSParams = req.query().unwrap();
+pub async fn pdf(mut req: Request<()>, bucket: &str) -> tide::Result {
+    let params: PDFRequestQSParams = match req.method() {
+        Method::Post => match Content::read(&mut req).await? {
+            Some(Content::Html(html)) => PDFRequestQSParams {
+                html: Some(html),
+                ..req.query()?
+            },
+            Some(Content::Markdown(markdown)) => PDFRequestQSParams {
+                markdown: Some(markdown),
+                ..req.query()?
+            },
+            None => req.body_json().await?,
+        },
+        _ => req.query()?,
+    };
+    enqueue(params, bucket).await
+}
 
+/// Prints what `params` ask for, shared by every endpoint that produces pdfs.
+pub async fn enqueue(params: PDFRequestQSParams, bucket: &str) -> tide::Result {
     let filename = params.filename();
     let path = params.path();
     let op = DAL_OP_MAP.get(bucket).unwrap();
 
     let PDFRequestQSParams {
         url,
+        html,
+        markdown,
+        base_url,
         scale,
+        landscape,
+        paper,
+        paper_width,
+        paper_height,
+        margin,
+        margin_top,
+        margin_bottom,
+        margin_left,
+        margin_right,
+        page_ranges,
+        prefer_css_page_size,
+        print_background,
+        header_template,
+        footer_template,
+        color_scheme,
+        media_type,
+        reduced_motion,
+        timezone,
+        locale,
+        accept_language,
+        geolocation,
+        headers,
+        cookies,
+        referrer,
+        basic_auth,
+        block_resources,
+        block_urls,
+        allow_urls,
+        block_ads,
+        css,
+        js,
+        hide_selectors,
+        hide_cookie_banners,
+        actions,
         omit_background,
+        wait_until,
+        wait_timeout,
+        virtual_time_budget,
         ttl,
     } = params;
 
-    if op.is_exist(&path).await.unwrap() && ttl.is_some() {
-      if op.stat(&path).await.unwrap().last_modified().unwrap().checked_add_signed(TimeDelta::new(ttl.unwrap().try_into().unwrap(), 0).unwrap()).unwrap() >= Local::now() {
+    if pool::is_fresh(op, &path, ttl).await {
         let signed_url = signed_url(op, &path, bucket).await.unwrap();
         return Ok(Redirect::new(signed_url).into());
-      }
     }
 
     let (tx, rx) = oneshot_channel();
@@ -164,77 +270,306 @@ pub async fn pdf(req: Request<()>, bucket: &str) -> tide::Result {
         .clone()
         .unwrap();
 
-    let _ = PDF_TASK_CHANNEL
-        .0
-        .unbounded_send(PDFTask {
+    actions::validate(&actions).map_err(|err| Error::from_str(err.status(), err.to_string()))?;
+
+    let wait_timeout = wait_timeout
+        .or(default_pdf_task_params.wait_timeout)
+        .unwrap_or(DEFAULT_WAIT_TIMEOUT);
+    wait::validate_timeout(wait_timeout)
+        .map_err(|err| Error::from_str(err.status(), err.to_string()))?;
+
+    let header_template =
+        header_template.or_else(|| default_pdf_task_params.header_template.clone());
+    let footer_template =
+        footer_template.or_else(|| default_pdf_task_params.footer_template.clone());
+    let omit_background = omit_background
+        .or(default_pdf_task_params.omit_background)
+        .unwrap_or(false);
+
+    let ads = match block_ads.or(default_pdf_task_params.block_ads).unwrap_or(false) {
+        true => Some(AdFilter::get(bucket).ok_or_else(|| {
+            let err = WorkerError::InvalidParam {
+                name: "block_ads".to_owned(),
+                value: "true".to_owned(),
+            };
+            Error::from_str(err.status(), err.to_string())
+        })?),
+        false => None,
+    };
+
+    let (url, document) = document::resolve(
+        op,
+        &SERVER_CONFIG.buckets.get(bucket).unwrap().markdown_stylesheet,
+        url,
+        html,
+        markdown,
+        base_url,
+    )
+    .await
+    .map_err(|err| Error::from_str(err.status(), err.to_string()))?;
+    document::check_scoped(
+        &url,
+        document.is_some(),
+        &[
+            ("geolocation", geolocation.is_some()),
+            ("cookies", cookies.is_some()),
+        ],
+        &[
+            ("headers", headers.is_some()),
+            ("basic_auth", basic_auth.is_some()),
+        ],
+    )
+    .map_err(|err| Error::from_str(err.status(), err.to_string()))?;
+
+    let injection = Injection::resolve(
+        op,
+        &default_pdf_task_params.css_files,
+        &default_pdf_task_params.js_files,
+        css.or_else(|| default_pdf_task_params.css.clone()),
+        js.or_else(|| default_pdf_task_params.js.clone()),
+        hide_selectors.or_else(|| default_pdf_task_params.hide_selectors.clone()),
+    )
+    .await
+    .map_err(|err| Error::from_str(err.status(), err.to_string()))?;
+
+    PDF_TASKS
+        .push(PDFTask {
             0: tx,
             1: PDFTaskInner {
                 bucket: bucket.to_owned(),
                 filename,
+                document,
+                omit_background,
+                media: Media {
+                    color_scheme: color_scheme.or(default_pdf_task_params.color_scheme),
+                    media_type: media_type.or(default_pdf_task_params.media_type),
+                    reduced_motion: reduced_motion.or(default_pdf_task_params.reduced_motion),
+                },
+                locale: Locale {
+                    timezone: timezone.or_else(|| default_pdf_task_params.timezone.clone()),
+                    locale: locale.or_else(|| default_pdf_task_params.locale.clone()),
+                    accept_language: accept_language
+                        .or_else(|| default_pdf_task_params.accept_language.clone()),
+                    geolocation: geolocation.or_else(|| default_pdf_task_params.geolocation.clone()),
+                },
+                overrides: RequestOverrides {
+                    headers: merged(&default_pdf_task_params.headers, headers),
+                    cookies: merged(&default_pdf_task_params.cookies, cookies),
+                    basic_auth: basic_auth.or_else(|| default_pdf_task_params.basic_auth.clone()),
+                    blocking: Blocking {
+                        resources: block_resources
+                            .or_else(|| default_pdf_task_params.block_resources.clone())
+                            .unwrap_or_default(),
+                        urls: block_urls
+                            .or_else(|| default_pdf_task_params.block_urls.clone())
+                            .unwrap_or_default(),
+                        ads,
+                        allow_urls,
+                    },
+                },
+                injection,
+                cookie_banners: hide_cookie_banners
+                    .or(default_pdf_task_params.hide_cookie_banners)
+                    .unwrap_or(false)
+                    .then(|| CookieBannerRules::for_bucket(bucket)),
+                actions,
+                wait_until: wait_until
+                    .or_else(|| default_pdf_task_params.wait_until.clone())
+                    .unwrap_or_default(),
+                wait_timeout,
+                virtual_time_budget: virtual_time_budget
+                    .or(default_pdf_task_params.virtual_time_budget),
             },
             2: NavigateParams {
-                url: url.to_string(),
-                referrer: None,
+                url,
+                referrer: referrer.or_else(|| default_pdf_task_params.referrer.clone()),
                 transition_type: None,
                 frame_id: None,
                 referrer_policy: None,
             },
             3: PrintToPdfParams {
-              landscape: None,
-              display_header_footer: None,
-              print_background: omit_background,
+              landscape: landscape.or(default_pdf_task_params.landscape),
+              display_header_footer: Some(
+                  header_template.is_some() || footer_template.is_some(),
+              ),
+              // unset unless asked for, Chrome's default then applies
+              print_background: print_background
+                  .or(default_pdf_task_params.print_background),
               scale: Some(Into::<f64>::into(
                     scale.unwrap_or(default_pdf_task_params.scale.unwrap()),
                 ) / 10.0),
-              paper_width: None,
-              paper_height: None,
-              margin_top: None,
-              margin_bottom: None,
-              margin_left: None,
-              margin_right: None,
-              page_ranges: None,
-              header_template: None,
-              footer_template: None,
-              prefer_css_page_size: None,
+              paper_width: paper_width
+                  .map(|width| width.inches())
+                  .or(paper.as_ref().map(|paper| paper.size().0))
+                  .or(default_pdf_task_params.paper_width.as_ref().map(|width| width.inches()))
+                  .or(default_pdf_task_params.paper.as_ref().map(|paper| paper.size().0)),
+              paper_height: paper_height
+                  .map(|height| height.inches())
+                  .or(paper.as_ref().map(|paper| paper.size().1))
+                  .or(default_pdf_task_params.paper_height.as_ref().map(|height| height.inches()))
+                  .or(default_pdf_task_params.paper.as_ref().map(|paper| paper.size().1)),
+              margin_top: margin_top
+                  .or_else(|| margin.clone())
+                  .or_else(|| default_pdf_task_params.margin_top.clone())
+                  .or_else(|| default_pdf_task_params.margin.clone())
+                  .map(|margin| margin.inches()),
+              margin_bottom: margin_bottom
+                  .or_else(|| margin.clone())
+                  .or_else(|| default_pdf_task_params.margin_bottom.clone())
+                  .or_else(|| default_pdf_task_params.margin.clone())
+                  .map(|margin| margin.inches()),
+              margin_left: margin_left
+                  .or_else(|| margin.clone())
+                  .or_else(|| default_pdf_task_params.margin_left.clone())
+                  .or_else(|| default_pdf_task_params.margin.clone())
+                  .map(|margin| margin.inches()),
+              margin_right: margin_right
+                  .or_else(|| margin.clone())
+                  .or_else(|| default_pdf_task_params.margin_right.clone())
+                  .or_else(|| default_pdf_task_params.margin.clone())
+                  .map(|margin| margin.inches()),
+              page_ranges: page_ranges.or_else(|| default_pdf_task_params.page_ranges.clone()),
+              header_template,
+              footer_template,
+              prefer_css_page_size: prefer_css_page_size
+                  .or(default_pdf_task_params.prefer_css_page_size),
               transfer_mode: None,
             },
-        })
-        .unwrap();
-
-    if let Ok(Some(filename)) = rx.await {
-        info!("redirect to {:#}", filename);
-        return Ok(Redirect::new(filename).into());
-    }
+        });
 
-    Err(Error::from_str(StatusCode::InternalServerError, ""))
+    pool::reply(rx).await
 }
 
 struct PDFTaskInner {
     bucket: String,
     filename: String,
+    /// loaded in place of the navigation url, see `document::resolve`
+    document: Option<String>,
+    omit_background: bool,
+    media: Media,
+    locale: Locale,
+    overrides: RequestOverrides,
+    injection: Injection,
+    cookie_banners: Option<CookieBannerRules>,
+    actions: Vec<ActionStep>,
+    wait_until: WaitUntil,
+    wait_timeout: u64,
+    virtual_time_budget: Option<u64>,
 }
 
 struct PDFTask(
-    OneshotSender<Option<String>>,
+    OneshotSender<Result<TaskOutput, WorkerError>>,
     PDFTaskInner,
     NavigateParams,
     PrintToPdfParams,
 );
 
+use std::collections::BTreeMap;
 use std::hash::Hash;
+use std::time::{Duration, Instant};
 
 use crate::config::{DAL_OP_MAP, SERVER_CONFIG};
+use crate::error::WorkerError;
 use crate::util::hash::{calculate_hash, calculate_hash_str};
 use crate::util::signature_v4::{signed_url};
-
+use crate::worker::actions::{self, ActionStep};
+use crate::worker::block::{Blocking, ResourceKind};
+use crate::worker::consent::CookieBannerRules;
+use crate::worker::document::{self, Content};
+use crate::worker::filter_list::AdFilter;
+use crate::worker::emulation::{self, ColorScheme, Geolocation, Locale, Media, MediaType};
+use crate::worker::inject::{self, Injection};
+use crate::worker::network::{self, merged, RequestOverrides};
+use crate::worker::output::TaskOutput;
+use crate::worker::paper::{Length, PaperFormat};
+use crate::worker::pool::{self, TaskQueue};
+use crate::worker::wait::{self, navigate, VirtualTime, WaitUntil, DEFAULT_WAIT_TIMEOUT};
+
+/// Query string of a GET, or the JSON body of a POST request.
 #[derive(Debug, Serialize, Deserialize, Clone, Hash)]
 pub struct PDFRequestQSParams {
-    pub url: Url,
+    /// exactly one of `url`, `html` and `markdown` is required
+    pub url: Option<Url>,
+    /// document to render, or the body of a `text/html` POST
+    pub html: Option<String>,
+    /// rendered with the bucket `markdown_stylesheet`, or the body of a `text/markdown` POST
+    pub markdown: Option<String>,
+    /// relative urls in `html` and `markdown` resolve against it, `headers` and
+    /// `basic_auth` go to its origin, `cookies` and `geolocation` aren't taken
+    pub base_url: Option<Url>,
 
     pub scale: Option<u8>,
     pub ttl: Option<u64>,
 
+    pub landscape: Option<bool>,
+    /// named preset, e.g. `a4`, `letter`, `legal`
+    pub paper: Option<PaperFormat>,
+    /// overrides the width of `paper`
+    pub paper_width: Option<Length>,
+    /// overrides the height of `paper`
+    pub paper_height: Option<Length>,
+    /// shorthand for all four margins
+    pub margin: Option<Length>,
+    pub margin_top: Option<Length>,
+    pub margin_bottom: Option<Length>,
+    pub margin_left: Option<Length>,
+    pub margin_right: Option<Length>,
+    /// e.g. `1-5, 8, 11-13`
+    pub page_ranges: Option<String>,
+    pub prefer_css_page_size: Option<bool>,
+    pub print_background: Option<bool>,
+    pub header_template: Option<String>,
+    pub footer_template: Option<String>,
+
+    pub color_scheme: Option<ColorScheme>,
+    /// `print` unless set, as for any printed page
+    pub media_type: Option<MediaType>,
+    pub reduced_motion: Option<bool>,
+
+    /// IANA timezone id, e.g. `Europe/Berlin`
+    pub timezone: Option<String>,
+    /// BCP 47 tag, e.g. `de-DE`
+    pub locale: Option<String>,
+    /// defaults to `locale`
+    pub accept_language: Option<String>,
+    /// `lat,lon[,accuracy]`
+    pub geolocation: Option<Geolocation>,
+
+    /// sent to the origin of `url` only, e.g. `headers[X-Token]=...`
+    pub headers: Option<BTreeMap<String, String>>,
+    pub cookies: Option<BTreeMap<String, String>>,
+    pub referrer: Option<String>,
+    /// `user:password`
+    pub basic_auth: Option<String>,
+
+    /// `image`, `media`, `font`, `stylesheet`, `script` or `xhr`
+    pub block_resources: Option<Vec<ResourceKind>>,
+    /// url globs, `*` and `?` wildcards
+    pub block_urls: Option<Vec<String>>,
+    /// url globs, requests matching none of them are blocked
+    pub allow_urls: Option<Vec<String>>,
+    /// block requests matched by the bucket `filter_lists`
+    pub block_ads: Option<bool>,
+
+    /// injected after load, before `wait_until` is done
+    pub css: Option<String>,
+    pub js: Option<String>,
+    /// css selector list of elements to hide
+    pub hide_selectors: Option<String>,
+    /// accept and hide consent dialogs, see `consent::CookieBannerRules`
+    pub hide_cookie_banners: Option<bool>,
+
+    /// steps to run before capture, JSON body only
+    #[serde(default)]
+    pub actions: Vec<ActionStep>,
+
     pub omit_background: Option<bool>,
+
+    pub wait_until: Option<WaitUntil>,
+    /// milliseconds
+    pub wait_timeout: Option<u64>,
+    /// milliseconds of virtual time, see `Emulation.setVirtualTimePolicy`
+    pub virtual_time_budget: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Hash)]
@@ -244,14 +579,76 @@ pub struct PDFRequestParams {
     #[serde(default = "default_ttl")]
     pub ttl: Option<u64>,
 
+    pub landscape: Option<bool>,
+    /// named preset, e.g. `a4`, `letter`, `legal`
+    pub paper: Option<PaperFormat>,
+    /// overrides the width of `paper`, but not a request's `paper`
+    pub paper_width: Option<Length>,
+    /// overrides the height of `paper`, but not a request's `paper`
+    pub paper_height: Option<Length>,
+    /// shorthand for all four margins, a request's `margin` beats the sides set here
+    pub margin: Option<Length>,
+    pub margin_top: Option<Length>,
+    pub margin_bottom: Option<Length>,
+    pub margin_left: Option<Length>,
+    pub margin_right: Option<Length>,
+    /// e.g. `1-5, 8, 11-13`
+    pub page_ranges: Option<String>,
+    pub prefer_css_page_size: Option<bool>,
+    pub print_background: Option<bool>,
+    pub header_template: Option<String>,
+    pub footer_template: Option<String>,
+
+    pub color_scheme: Option<ColorScheme>,
+    /// `print` unless set, as for any printed page
+    pub media_type: Option<MediaType>,
+    pub reduced_motion: Option<bool>,
+
+    /// IANA timezone id, e.g. `Europe/Berlin`
+    pub timezone: Option<String>,
+    /// BCP 47 tag, e.g. `de-DE`
+    pub locale: Option<String>,
+    /// defaults to `locale`
+    pub accept_language: Option<String>,
+    /// `lat,lon[,accuracy]`
+    pub geolocation: Option<Geolocation>,
+
+    /// merged with the request ones, the request wins
+    pub headers: Option<BTreeMap<String, String>>,
+    pub cookies: Option<BTreeMap<String, String>>,
+    pub referrer: Option<String>,
+    /// `user:password`
+    pub basic_auth: Option<String>,
+
+    /// `image`, `media`, `font`, `stylesheet`, `script` or `xhr`
+    pub block_resources: Option<Vec<ResourceKind>>,
+    /// url globs, `*` and `?` wildcards
+    pub block_urls: Option<Vec<String>>,
+    /// block requests matched by the bucket `filter_lists`
+    pub block_ads: Option<bool>,
+
+    /// paths in the bucket storage, injected before `css` and `js`
+    pub css_files: Option<Vec<String>>,
+    pub js_files: Option<Vec<String>>,
+    pub css: Option<String>,
+    pub js: Option<String>,
+    pub hide_selectors: Option<String>,
+    pub hide_cookie_banners: Option<bool>,
+
     pub omit_background: Option<bool>,
+
+    #[serde(default = "default_wait_until")]
+    pub wait_until: Option<WaitUntil>,
+    #[serde(default = "default_wait_timeout")]
+    pub wait_timeout: Option<u64>,
+    pub virtual_time_budget: Option<u64>,
 }
 
 impl PDFRequestQSParams {
     pub fn filename(&self) -> String {
         format!(
             "{:#}/{:x}",
-            calculate_hash_str(&self.url.origin().ascii_serialization()),
+            calculate_hash_str(&document::origin(&self.url, &self.base_url)),
             calculate_hash(self)
         )
     }
@@ -268,8 +665,45 @@ impl PDFRequestQSParams {
 pub fn default_buckets_pdf_task_params() -> Option<PDFRequestParams> {
     Some(PDFRequestParams {
         scale: default_scale(),
+        landscape: None,
+        paper: None,
+        paper_width: None,
+        paper_height: None,
+        margin: None,
+        margin_top: None,
+        margin_bottom: None,
+        margin_left: None,
+        margin_right: None,
+        page_ranges: None,
+        prefer_css_page_size: None,
+        print_background: None,
+        header_template: None,
+        footer_template: None,
+        color_scheme: None,
+        media_type: None,
+        reduced_motion: None,
+        timezone: None,
+        locale: None,
+        accept_language: None,
+        geolocation: None,
+        headers: None,
+        cookies: None,
+        referrer: None,
+        basic_auth: None,
+        block_resources: None,
+        block_urls: None,
+        block_ads: None,
+        css_files: None,
+        js_files: None,
+        css: None,
+        js: None,
+        hide_selectors: None,
+        hide_cookie_banners: None,
         omit_background: None,
         ttl: default_ttl(),
+        wait_until: default_wait_until(),
+        wait_timeout: default_wait_timeout(),
+        virtual_time_budget: None,
     })
 }
 
@@ -280,3 +714,11 @@ fn default_scale() -> Option<u8> {
 fn default_ttl() -> Option<u64> {
     Some(60)
 }
+
+fn default_wait_until() -> Option<WaitUntil> {
+    Some(WaitUntil::NetworkIdle0)
+}
+
+fn default_wait_timeout() -> Option<u64> {
+    Some(DEFAULT_WAIT_TIMEOUT)
+}
\ No newline at end of file
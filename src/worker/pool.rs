@@ -0,0 +1,68 @@
+use chrono::{Local, TimeDelta};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::channel::oneshot::Receiver as OneshotReceiver;
+use futures::lock::Mutex;
+use futures::StreamExt;
+use opendal::Operator;
+use tide::log::info;
+use tide::{Error, StatusCode};
+
+use crate::error::WorkerError;
+use crate::worker::output::TaskOutput;
+
+/// Tasks of one kind, handlers push and the pooled workers of that kind take
+/// them in turn.
+pub struct TaskQueue<T> {
+    tx: UnboundedSender<T>,
+    rx: Mutex<UnboundedReceiver<T>>,
+}
+
+impl<T> TaskQueue<T> {
+    pub fn new() -> Self {
+        let (tx, rx) = unbounded();
+        TaskQueue {
+            tx,
+            rx: Mutex::new(rx),
+        }
+    }
+
+    pub fn push(&self, task: T) {
+        self.tx.unbounded_send(task).unwrap();
+    }
+
+    /// Waits for the next task, one worker at a time.
+    pub async fn next(&self) -> Option<T> {
+        self.rx.lock().await.next().await
+    }
+}
+
+impl<T> Default for TaskQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether `path` was stored less than `ttl` seconds ago, never without a `ttl`.
+pub async fn is_fresh(op: &Operator, path: &str, ttl: Option<u64>) -> bool {
+    let (Some(ttl), Ok(metadata)) = (ttl, op.stat(path).await) else {
+        return false;
+    };
+    match (metadata.last_modified(), i64::try_from(ttl)) {
+        (Some(modified), Ok(ttl)) => TimeDelta::try_seconds(ttl)
+            .and_then(|ttl| modified.checked_add_signed(ttl))
+            .map_or(false, |expires| expires >= Local::now()),
+        _ => false,
+    }
+}
+
+/// Answers the request with what the worker sent back for its task.
+pub async fn reply(rx: OneshotReceiver<Result<TaskOutput, WorkerError>>) -> tide::Result {
+    match rx.await {
+        Ok(Ok(output)) => {
+            info!("redirect to {:#}", output.signed_url);
+            Ok(output.into_response())
+        }
+        Ok(Err(err)) => Err(Error::from_str(err.status(), err.to_string())),
+        Err(_) => Err(Error::from_str(StatusCode::InternalServerError, "")),
+    }
+}
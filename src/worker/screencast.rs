@@ -0,0 +1,273 @@
+use std::time::Duration;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chromiumoxide::Page;
+use chromiumoxide_cdp::cdp::browser_protocol::page::{
+    EventScreencastFrame, ScreencastFrameAckParams, StartScreencastFormat, StartScreencastParams,
+    StopScreencastParams,
+};
+use futures::StreamExt;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::error::{EncodingError, ImageFormatHint};
+use image::imageops::{self, FilterType};
+use image::{Delay, Frame, ImageError, ImageFormat, RgbaImage};
+use tokio::time::{interval, timeout, MissedTickBehavior};
+
+use crate::error::WorkerError;
+
+/// Longest recording in milliseconds.
+pub const MAX_DURATION: u64 = 10_000;
+
+/// Highest frame rate, Chrome rarely paints faster.
+pub const MAX_FPS: u8 = 30;
+
+/// Largest width or height of the frames in pixels.
+pub const MAX_SIZE: u32 = 1_920;
+
+/// Most pixels of all frames of a recording, they are held decoded until
+/// encoded, 4 bytes each.
+pub const MAX_TOTAL_PIXELS: u64 = 64 << 20;
+
+/// Screencast frames are only sent when something changed, so the wait for
+/// the first one is bounded separately.
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Scrolls the page by `distance` css pixels, linearly over `duration` milliseconds.
+const SCROLL_JS: &str = r#"
+((distance, duration) => {
+    const from = window.scrollY;
+    const start = performance.now();
+    const step = (now) => {
+        const progress = Math.min(1, (now - start) / duration);
+        window.scrollTo(0, from + distance * progress);
+        if (progress < 1) requestAnimationFrame(step);
+    };
+    requestAnimationFrame(step);
+})
+"#;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum AnimationFormat {
+    Webp,
+    Gif,
+    Apng,
+}
+
+impl AnimationFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AnimationFormat::Webp => "webp",
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "png",
+        }
+    }
+}
+
+/// Checks the recording params against `MAX_FPS`, `MAX_DURATION`, `MAX_SIZE`
+/// and the `MAX_TOTAL_PIXELS` of all frames, assuming square ones.
+pub fn validate(fps: u8, duration: u64, max_size: u32) -> Result<(), WorkerError> {
+    let pixels = frame_count(fps, duration) as u64 * u64::from(max_size).pow(2);
+    for (name, valid, value) in [
+        ("fps", (1..=MAX_FPS).contains(&fps), fps.to_string()),
+        (
+            "duration",
+            (1..=MAX_DURATION).contains(&duration),
+            duration.to_string(),
+        ),
+        (
+            "max_size",
+            (1..=MAX_SIZE).contains(&max_size) && pixels <= MAX_TOTAL_PIXELS,
+            max_size.to_string(),
+        ),
+    ] {
+        if !valid {
+            return Err(WorkerError::InvalidParam {
+                name: name.to_owned(),
+                value,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn frame_count(fps: u8, duration: u64) -> usize {
+    (duration * u64::from(fps) / 1000).max(1) as usize
+}
+
+/// Records `duration` milliseconds of the page at `fps` frames per second,
+/// fitting the frames into `max_size` pixels. The page is scrolled down by
+/// `scroll_by` css pixels meanwhile, if set.
+///
+/// Chrome only sends a frame when the page changed, the last one is repeated
+/// until the next arrives.
+pub async fn record(
+    page: &Page,
+    fps: u8,
+    duration: u64,
+    max_size: u32,
+    scroll_by: Option<u32>,
+) -> Result<Vec<RgbaImage>, WorkerError> {
+    validate(fps, duration, max_size)?;
+    let mut events = page.event_listener::<EventScreencastFrame>().await?;
+    page.execute(StartScreencastParams {
+        format: Some(StartScreencastFormat::Jpeg),
+        quality: Some(90),
+        max_width: Some(max_size.into()),
+        max_height: Some(max_size.into()),
+        every_nth_frame: None,
+    })
+    .await?;
+
+    let recorded = async {
+        let mut latest = match timeout(FIRST_FRAME_TIMEOUT, events.next()).await {
+            Ok(Some(event)) => ack(page, &event).await?,
+            _ => {
+                return Err(WorkerError::Timeout(
+                    FIRST_FRAME_TIMEOUT.as_millis() as u64,
+                    "the first screencast frame".to_owned(),
+                ))
+            }
+        };
+        if let Some(distance) = scroll_by {
+            page.evaluate_expression(format!("({})({}, {})", SCROLL_JS, distance, duration))
+                .await?;
+        }
+
+        let count = frame_count(fps, duration);
+        let mut frames = Vec::with_capacity(count);
+        let mut ticks = interval(Duration::from_secs(1) / u32::from(fps));
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        while frames.len() < count {
+            tokio::select! {
+                Some(event) = events.next() => latest = ack(page, &event).await?,
+                _ = ticks.tick() => frames.push(decode(&latest, frames.first())?),
+            }
+        }
+        Ok(frames)
+    }
+    .await;
+
+    page.execute(StopScreencastParams {}).await?;
+    recorded
+}
+
+/// Acknowledges `event`, Chrome stops sending frames otherwise.
+async fn ack(page: &Page, event: &EventScreencastFrame) -> Result<Vec<u8>, WorkerError> {
+    page.execute(ScreencastFrameAckParams::new(event.session_id))
+        .await?;
+    Ok(STANDARD.decode(event.data.as_ref()).unwrap_or_default())
+}
+
+/// Frames have to share the size of the first one.
+fn decode(buf: &[u8], first: Option<&RgbaImage>) -> Result<RgbaImage, WorkerError> {
+    let frame = image::load_from_memory_with_format(buf, ImageFormat::Jpeg)?.to_rgba8();
+    Ok(match first {
+        Some(first) if first.dimensions() != frame.dimensions() => {
+            imageops::resize(&frame, first.width(), first.height(), FilterType::Triangle)
+        }
+        _ => frame,
+    })
+}
+
+fn encoding_error(format: ImageFormat, err: impl std::fmt::Debug) -> WorkerError {
+    ImageError::Encoding(EncodingError::new(
+        ImageFormatHint::Exact(format),
+        format!("{:?}", err),
+    ))
+    .into()
+}
+
+/// Encodes `frames` as an endlessly looping animation.
+pub fn encode(
+    frames: Vec<RgbaImage>,
+    fps: u8,
+    format: AnimationFormat,
+) -> Result<Vec<u8>, WorkerError> {
+    let (width, height) = frames[0].dimensions();
+    let frame_ms = 1000 / i32::from(fps);
+    let mut buf = vec![];
+    match format {
+        AnimationFormat::Gif => {
+            let mut encoder = GifEncoder::new_with_speed(&mut buf, 10);
+            encoder.set_repeat(Repeat::Infinite)?;
+            encoder.encode_frames(frames.into_iter().map(|frame| {
+                Frame::from_parts(frame, 0, 0, Delay::from_numer_denom_ms(1000, fps.into()))
+            }))?;
+        }
+        AnimationFormat::Apng => {
+            let mut encoder = png::Encoder::new(&mut buf, width, height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder
+                .set_animated(frames.len() as u32, 0)
+                .and_then(|_| encoder.set_frame_delay(1, fps.into()))
+                .map_err(|err| encoding_error(ImageFormat::Png, err))?;
+            let mut writer = encoder
+                .write_header()
+                .map_err(|err| encoding_error(ImageFormat::Png, err))?;
+            for frame in &frames {
+                writer
+                    .write_image_data(frame.as_raw())
+                    .map_err(|err| encoding_error(ImageFormat::Png, err))?;
+            }
+            writer
+                .finish()
+                .map_err(|err| encoding_error(ImageFormat::Png, err))?;
+        }
+        AnimationFormat::Webp => {
+            let mut encoder = webp_animation::Encoder::new((width, height))
+                .map_err(|err| encoding_error(ImageFormat::WebP, err))?;
+            for (index, frame) in frames.iter().enumerate() {
+                encoder
+                    .add_frame(frame.as_raw(), index as i32 * frame_ms)
+                    .map_err(|err| encoding_error(ImageFormat::WebP, err))?;
+            }
+            buf = encoder
+                .finalize(frames.len() as i32 * frame_ms)
+                .map_err(|err| encoding_error(ImageFormat::WebP, err))?
+                .to_vec();
+        }
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_each_param() {
+        assert!(validate(10, 3_000, 640).is_ok());
+        assert!(validate(MAX_FPS, 1, MAX_SIZE).is_ok());
+        for (fps, duration, max_size, param) in [
+            (0, 1_000, 640, "fps"),
+            (MAX_FPS + 1, 1_000, 640, "fps"),
+            (10, 0, 640, "duration"),
+            (10, MAX_DURATION + 1, 640, "duration"),
+            (10, 1_000, 0, "max_size"),
+            (10, 1_000, MAX_SIZE + 1, "max_size"),
+        ] {
+            match validate(fps, duration, max_size) {
+                Err(WorkerError::InvalidParam { name, .. }) => assert_eq!(name, param),
+                other => panic!("{} {} {}: {:?}", fps, duration, max_size, other),
+            }
+        }
+    }
+
+    #[test]
+    fn caps_the_pixels_of_all_frames() {
+        // 300 frames of 1920² would be about 4.4 GB decoded
+        assert!(validate(MAX_FPS, MAX_DURATION, MAX_SIZE).is_err());
+        // exactly 64 frames of 1024²
+        assert!(validate(16, 4_000, 1_024).is_ok());
+        assert!(validate(17, 4_000, 1_024).is_err());
+    }
+
+    #[test]
+    fn records_at_least_one_frame() {
+        assert_eq!(frame_count(1, 1), 1);
+        assert_eq!(frame_count(24, 1_500), 36);
+        assert_eq!(frame_count(MAX_FPS, MAX_DURATION), 300);
+    }
+}
@@ -1,32 +1,24 @@
 use chromiumoxide::{page::ScreenshotParams, Page};
 
-use chromiumoxide_cdp::cdp::browser_protocol::emulation::SetDeviceMetricsOverrideParams;
-use futures::lock::Mutex;
 use lazy_static::lazy_static;
 
-use tide::{Error, Redirect, Request, StatusCode};
+use tide::{http::Method, Error, Redirect, Request};
 
+use chromiumoxide_cdp::cdp::browser_protocol::browser::BrowserContextId;
 use chromiumoxide_cdp::cdp::browser_protocol::page::{
-    CaptureScreenshotFormat, CaptureScreenshotParams, NavigateParams, Viewport,
+    CaptureScreenshotParams, NavigateParams, Viewport,
 };
-use futures::channel::mpsc::{unbounded, Sender, UnboundedReceiver, UnboundedSender};
+use futures::channel::mpsc::Sender;
 use futures::channel::oneshot::{channel as oneshot_channel, Sender as OneshotSender};
-use futures::StreamExt;
 
 use serde::{Deserialize, Serialize};
 
-use tide::log::{debug, info};
+use tide::log::debug;
 
 use url::Url;
 
 lazy_static! {
-    static ref SCREENSHOT_TASK_CHANNEL: (
-        UnboundedSender<ScreenshotTask>,
-        Mutex<UnboundedReceiver<ScreenshotTask>>
-    ) = {
-        let (tx, rx) = unbounded();
-        (tx, Mutex::new(rx))
-    };
+    static ref SCREENSHOT_TASKS: TaskQueue<ScreenshotTask> = TaskQueue::new();
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -40,22 +32,15 @@ pub struct Claims {
 pub struct ScreenshotWorker {}
 
 impl ScreenshotWorker {
-    pub async fn new(id: usize, page: Page, ptx: Sender<usize>) {
+    pub async fn new(id: usize, page: Page, context: BrowserContextId, ptx: Sender<usize>) {
         debug!("worker {:#} create {:?}", id, page);
         tokio::task::spawn(async move {
             debug!("worker {:#} start", id);
             loop {
                 if let Some(ScreenshotTask(tx, inner, navigate_params, cdp_params)) =
-                    SCREENSHOT_TASK_CHANNEL.1.lock().await.next().await
+                    SCREENSHOT_TASKS.next().await
                 {
-                    match worker(id, &page, inner, navigate_params, cdp_params).await {
-                        Ok(uri) => {
-                            tx.send(Some(uri));
-                        }
-                        Err(_) => {
-                            tx.send(None);
-                        }
-                    }
+                    let _ = tx.send(worker(id, &page, &context, inner, navigate_params, cdp_params).await);
                 }
             }
             let _ = ptx.try_send(id).unwrap();
@@ -69,54 +54,110 @@ impl ScreenshotWorker {
 pub async fn worker(
     id: usize,
     page: &Page,
+    context: &BrowserContextId,
     inner: ScreenshotTaskInner,
     navigate_params: NavigateParams,
     cdp_params: CaptureScreenshotParams,
-) -> Result<String, ()> {
+) -> Result<TaskOutput, WorkerError> {
     debug!("worker {:#} recv {:#} {:?}", id, inner.filename, cdp_params);
+    let origin = Url::parse(&navigate_params.url)
+        .map(|url| url.origin().ascii_serialization())
+        .unwrap_or_default();
+    let locale = inner.locale.clone();
+    let result = capture(id, page, context, inner, &origin, navigate_params, cdp_params).await;
+
+    let _ = page.goto("about:blank").await;
+    let _ = emulation::reset(page).await;
+    let _ = locale.revoke(page, context, &origin).await;
+    let _ = network::reset(page).await;
+    let _ = inject::reset(page).await;
+
+    result
+}
+
+async fn capture(
+    id: usize,
+    page: &Page,
+    context: &BrowserContextId,
+    inner: ScreenshotTaskInner,
+    origin: &str,
+    navigate_params: NavigateParams,
+    cdp_params: CaptureScreenshotParams,
+) -> Result<TaskOutput, WorkerError> {
     let op = DAL_OP_MAP.get(&inner.bucket).unwrap();
-    let filename = format!(
-        "{:#}.{:#}",
-        inner.filename,
-        match cdp_params.format.clone() {
-            Some(CaptureScreenshotFormat::Jpeg) => "jpg",
-            Some(CaptureScreenshotFormat::Webp) => "webp",
-            _ => "png",
-        }
+    let filename = format!("{:#}.{:#}", inner.filename, inner.encoding.format.extension());
+
+    inner.device.emulate(page).await?;
+    inner.media.emulate(page).await?;
+    inner.locale.emulate(page, context, origin).await?;
+    emulation::override_user_agent(
+        page,
+        inner.device.user_agent.as_deref(),
+        inner.locale.accept_language(),
     )
-    .to_owned();
+    .await?;
+    let _interception = inner.overrides.apply(page, &navigate_params.url).await?;
+    if !inner.injection.is_empty() || inner.cookie_banners.is_some() {
+        inject::bypass_csp(page).await?;
+    }
 
-    let _ = page.goto(navigate_params).await.unwrap();
+    let script_errors = navigate(
+        page,
+        navigate_params,
+        inner.document.as_deref(),
+        &inner.wait_until,
+        inner.wait_timeout,
+        async {
+            if let Some(cookie_banners) = &inner.cookie_banners {
+                cookie_banners.dismiss(page).await?;
+            }
+            inner.injection.apply(page).await
+        },
+    )
+    .await?;
 
-    let clip = &cdp_params.clip.unwrap();
+    if let Some(cookie_banners) = &inner.cookie_banners {
+        cookie_banners.accept(page).await?;
+    }
 
-    page.execute(SetDeviceMetricsOverrideParams::new(
-        clip.width as i64,
-        clip.height as i64,
-        2.0,
-        false,
-    ))
-    .await
-    .unwrap();
-
-    let img_buf = page
-        .screenshot(ScreenshotParams {
-            cdp_params: CaptureScreenshotParams {
-                format: cdp_params.format,
-                quality: cdp_params.quality,
-                clip: Some(Viewport { ..clip.clone() }),
-                from_surface: None,
-                capture_beyond_viewport: None,
-            },
-            full_page: inner.full_page,
-            omit_background: inner.omit_background,
-        })
-        .await
-        .unwrap();
+    actions::run(page, &inner.actions).await?;
+
+    if let Some(max_height) = inner.scroll_page {
+        scroll_through(page, max_height, inner.wait_timeout).await?;
+    }
+
+    if !inner.viewports.is_empty() {
+        return capture_viewports(id, page, op, &inner, &cdp_params, script_errors).await;
+    }
+
+    let (capture, truncated) = shoot(page, &inner, &inner.device, &cdp_params).await?;
+
+    if !inner.output_sizes.is_empty() {
+        let mut manifest = Manifest::default();
+        for rendered in transform::finish_sizes(
+            capture,
+            &inner.transform,
+            &inner.output_sizes,
+            &inner.encoding,
+        )? {
+            let path = format!(
+                "{:#}-{:#}.{:#}",
+                inner.filename,
+                rendered.width,
+                inner.encoding.format.extension()
+            );
+            manifest
+                .variants
+                .push(StoredVariant::write(op, path, None, rendered).await?);
+        }
+        return store_manifest(id, op, &inner, manifest, script_errors, truncated).await;
+    }
+
+    let rendered = transform::finish(capture, &inner.transform, &inner.encoding)?;
 
-    let file_size = &img_buf.len();
+    let file_size = &rendered.buf.len();
 
-    op.write(&filename, img_buf).await;
+    op.write(&filename, rendered.buf).await?;
 
     
     let signed_url = signed_url(op, &filename, &inner.bucket).await.unwrap();
@@ -128,142 +169,751 @@ pub async fn worker(
         file_size,
     );
 
-    page.goto("about:blank").await.unwrap();
+    return Ok(TaskOutput {
+        signed_url,
+        script_errors,
+        truncated,
+        variants: None,
+    });
+}
+
+/// Captures the region, the viewport or the full page of the loaded page
+/// as seen on `device`. The flag is set if the capture was truncated.
+async fn shoot(
+    page: &Page,
+    inner: &ScreenshotTaskInner,
+    device: &Device,
+    cdp_params: &CaptureScreenshotParams,
+) -> Result<(Capture, bool), WorkerError> {
+    let clip = &Viewport {
+        x: 0.0,
+        y: 0.0,
+        width: device.width.into(),
+        height: device.height.into(),
+        scale: cdp_params.clip.as_ref().unwrap().scale,
+    };
+
+    let region = match &inner.selector {
+        Some(selector) => Some(
+            element_box(page, selector)
+                .await?
+                .to_clip(inner.padding.into(), clip.scale),
+        ),
+        None => clip_region(page, inner, clip).await?,
+    };
+
+    let stitched = match (inner.full_page, &region) {
+        (Some(true), None) => {
+            let size = stitch::document_size(page).await?;
+            let ratio = clip.scale * device.device_scale_factor;
+            match size.1 * ratio > f64::from(MAX_TILE_HEIGHT.min(inner.max_output_height)) {
+                true => Some(
+                    stitch::capture(
+                        page,
+                        size,
+                        clip.width,
+                        clip.scale,
+                        device.device_scale_factor,
+                        inner.max_output_height,
+                        inner.omit_background,
+                    )
+                    .await?,
+                ),
+                false => None,
+            }
+        }
+        _ => None,
+    };
+
+    Ok(match stitched {
+        Some(stitched) => (Capture::Decoded(stitched.image), stitched.truncated),
+        None => (
+            Capture::Encoded(
+                page.screenshot(ScreenshotParams {
+                    cdp_params: CaptureScreenshotParams {
+                        format: cdp_params.format.clone(),
+                        quality: cdp_params.quality,
+                        clip: Some(region.clone().unwrap_or(Viewport { ..clip.clone() })),
+                        from_surface: None,
+                        capture_beyond_viewport: region.as_ref().map(|_| true),
+                    },
+                    // a region clip would be replaced by the full page one
+                    full_page: inner.full_page.filter(|_| region.is_none()),
+                    omit_background: inner.omit_background,
+                })
+                .await?,
+            ),
+            false,
+        ),
+    })
+}
+
+/// Re-emulates the loaded page for each of `viewports` and captures it,
+/// then puts them all on one contact sheet if `composite` is set.
+async fn capture_viewports(
+    id: usize,
+    page: &Page,
+    op: &Operator,
+    inner: &ScreenshotTaskInner,
+    cdp_params: &CaptureScreenshotParams,
+    script_errors: Vec<String>,
+) -> Result<TaskOutput, WorkerError> {
+    let mut manifest = Manifest::default();
+    let mut tiles = vec![];
+    let mut truncated = false;
+    for (index, (label, device)) in inner.viewports.iter().enumerate() {
+        device.reemulate(page).await?;
+        let (capture, cut) = shoot(page, inner, device, cdp_params).await?;
+        truncated |= cut;
+        let capture = match inner.composite {
+            true => {
+                let tile = capture.decode()?.to_rgba8();
+                tiles.push((label.clone(), tile.clone()));
+                Capture::Decoded(tile)
+            }
+            false => capture,
+        };
+        let path = format!(
+            "{:#}-{:#}.{:#}",
+            inner.filename,
+            index,
+            inner.encoding.format.extension()
+        );
+        let rendered = transform::finish(capture, &inner.transform, &inner.encoding)?;
+        manifest
+            .variants
+            .push(StoredVariant::write(op, path, Some(label.clone()), rendered).await?);
+    }
+
+    if inner.composite {
+        let path = format!(
+            "{:#}-composite.{:#}",
+            inner.filename,
+            inner.encoding.format.extension()
+        );
+        // tiles are unscaled, only the watermark is added
+        let rendered = transform::finish(
+            Capture::Decoded(contact_sheet::compose(&inner.bucket, &tiles)),
+            &Transform {
+                watermark: inner.transform.watermark,
+                ..Transform::default()
+            },
+            &inner.encoding,
+        )?;
+        manifest.composite = Some(StoredVariant::write(op, path, None, rendered).await?);
+    }
+
+    store_manifest(id, op, inner, manifest, script_errors, truncated).await
+}
+
+/// Stores the manifest of a multi-file capture and answers with its files.
+async fn store_manifest(
+    id: usize,
+    op: &Operator,
+    inner: &ScreenshotTaskInner,
+    manifest: Manifest,
+    script_errors: Vec<String>,
+    truncated: bool,
+) -> Result<TaskOutput, WorkerError> {
+    let path = format!("{:#}.json", inner.filename);
+    let variants = manifest.store(op, &inner.bucket, &path).await?;
+    debug!(
+        "worker {:#} save {:#} {:#} variants",
+        id,
+        &path,
+        variants.variants.len()
+    );
+
+    Ok(TaskOutput {
+        signed_url: signed_url(op, &path, &inner.bucket).await.unwrap(),
+        script_errors,
+        truncated,
+        variants: Some(variants),
+    })
+}
+
+/// Clip rectangle from `scroll_to` and `clip_*`, relative to the scroll position.
+/// `None` when the plain viewport is captured.
+async fn clip_region(
+    page: &Page,
+    inner: &ScreenshotTaskInner,
+    viewport: &Viewport,
+) -> Result<Option<Viewport>, WorkerError> {
+    if inner.scroll_to.is_none()
+        && inner.clip_x.is_none()
+        && inner.clip_y.is_none()
+        && inner.clip_width.is_none()
+        && inner.clip_height.is_none()
+    {
+        return Ok(None);
+    }
+
+    let (scroll_x, scroll_y) = match &inner.scroll_to {
+        Some(scroll_to) => scroll_to.apply(page).await?,
+        None => (0.0, 0.0),
+    };
 
-    return Ok(signed_url);
+    Ok(Some(Viewport {
+        x: scroll_x + f64::from(inner.clip_x.unwrap_or(0)),
+        y: scroll_y + f64::from(inner.clip_y.unwrap_or(0)),
+        width: inner.clip_width.map(f64::from).unwrap_or(viewport.width),
+        height: inner.clip_height.map(f64::from).unwrap_or(viewport.height),
+        scale: viewport.scale,
+    }))
 }
 
+pub async fn screenshot(mut req: Request<()>, bucket: &str) -> tide::Result {
+    let params: ScreenshotRequestQSParams = match req.method() {
+        Method::Post => match Content::read(&mut req).await? {
+            Some(Content::Html(html)) => ScreenshotRequestQSParams {
+                html: Some(html),
+                ..req.query()?
+            },
+            Some(Content::Markdown(markdown)) => ScreenshotRequestQSParams {
+                markdown: Some(markdown),
+                ..req.query()?
+            },
+            None => req.body_json().await?,
+        },
+        _ => req.query()?,
+    };
+    enqueue(params, bucket).await
+}
+
+/// Captures what `params` ask for, shared by every endpoint that produces screenshots.
+pub async fn enqueue(params: ScreenshotRequestQSParams, bucket: &str) -> tide::Result {
+    let default_screenshot_task_params = &SERVER_CONFIG
+        .buckets
+        .get(bucket)
+        .unwrap()
+        .screenshot_task_params
+        .clone()
+        .unwrap();
 
-pub async fn screenshot(req: Request<()>, bucket: &str) -> tide::Result {
-    let params: ScreenshotRequestQSParams = req.query().unwrap();
+    let watermark = Watermark::resolve(bucket, params.watermark)
+        .map_err(|err| Error::from_str(err.status(), err.to_string()))?;
 
-    let filename = params.filename();
-    let path = params.path();
+    let filename = params.filename(watermark);
+    let path = params.path(default_screenshot_task_params.format.unwrap(), watermark);
     let op = DAL_OP_MAP.get(bucket).unwrap();
 
     let ScreenshotRequestQSParams {
         url,
+        html,
+        markdown,
+        base_url,
         format,
         quality,
+        optimize_png,
         width,
         height,
         scale,
+        device,
+        dpr,
+        mobile,
+        user_agent,
+        color_scheme,
+        media_type,
+        reduced_motion,
+        timezone,
+        locale,
+        accept_language,
+        geolocation,
+        headers,
+        cookies,
+        referrer,
+        basic_auth,
+        block_resources,
+        block_urls,
+        allow_urls,
+        block_ads,
+        css,
+        js,
+        hide_selectors,
+        hide_cookie_banners,
+        actions,
         full_page,
         omit_background,
+        scroll_page,
+        scroll_max_height,
+        selector,
+        padding,
+        clip_x,
+        clip_y,
+        clip_width,
+        clip_height,
+        scroll_to,
+        thumb_width,
+        thumb_height,
+        crop,
+        fit,
+        output_sizes,
+        viewports,
+        composite,
+        watermark: _,
+        wait_until,
+        wait_timeout,
         ttl,
     } = params;
 
-    if op.is_exist(&path).await.unwrap() && ttl.is_some() {
-        if op.stat(&path).await.unwrap().last_modified().unwrap().checked_add_signed(chrono::TimeDelta::new(ttl.unwrap().try_into().unwrap(), 0).unwrap()).unwrap() >= chrono::Local::now() {
-        let signed_url = signed_url(op, &path, bucket).await.unwrap();
-        return Ok(Redirect::new(signed_url).into());
-      }
+    // answered with a manifest, exactly one of them may be set and not empty
+    let multi_file = output_sizes.is_some() || viewports.is_some();
+
+    if pool::is_fresh(op, &path, ttl).await {
+        match multi_file {
+            true => {
+                if let Some(variants) = Manifest::cached(op, bucket, &path).await {
+                    return Ok(variants.into_response());
+                }
+            }
+            false => {
+                let signed_url = signed_url(op, &path, bucket).await.unwrap();
+                return Ok(Redirect::new(signed_url).into());
+            }
+        }
     }
 
     let (tx, rx) = oneshot_channel();
 
-    let default_screenshot_task_params = &SERVER_CONFIG
-        .buckets
-        .get(bucket)
-        .unwrap()
-        .screenshot_task_params
-        .clone()
-        .unwrap();
+    let output_sizes = output_sizes.unwrap_or_default();
+    if (multi_file && output_sizes.is_empty() == viewports.as_ref().map_or(true, Vec::is_empty))
+        || (!output_sizes.is_empty() && transform::validate_sizes(&output_sizes).is_err())
+    {
+        let err = WorkerError::InvalidParam {
+            name: "output_sizes".to_owned(),
+            value: format!("{:?}", output_sizes),
+        };
+        return Err(Error::from_str(err.status(), err.to_string()));
+    }
+
+    let transform = Transform {
+        crop,
+        width: thumb_width,
+        height: thumb_height,
+        fit: fit.unwrap_or_default(),
+        watermark,
+    };
+    transform
+        .validate()
+        .map_err(|err| Error::from_str(err.status(), err.to_string()))?;
+    actions::validate(&actions).map_err(|err| Error::from_str(err.status(), err.to_string()))?;
+
+    let wait_timeout = wait_timeout
+        .or(default_screenshot_task_params.wait_timeout)
+        .unwrap_or(DEFAULT_WAIT_TIMEOUT);
+    wait::validate_timeout(wait_timeout)
+        .map_err(|err| Error::from_str(err.status(), err.to_string()))?;
+
+    let viewports = viewports.unwrap_or_default();
+    if viewports.len() > MAX_VIEWPORTS {
+        let err = WorkerError::InvalidParam {
+            name: "viewports".to_owned(),
+            value: format!("{:?}", viewports),
+        };
+        return Err(Error::from_str(err.status(), err.to_string()));
+    }
+    let viewports = viewports
+        .iter()
+        .map(|viewport| Ok((viewport.label().to_owned(), viewport.device(bucket)?)))
+        .collect::<Result<Vec<_>, WorkerError>>()
+        .map_err(|err| Error::from_str(err.status(), err.to_string()))?;
+    let composite = composite.unwrap_or(false);
+    if composite && !contact_sheet::has_labels(bucket) {
+        let err = WorkerError::InvalidParam {
+            name: "composite".to_owned(),
+            value: "needs a label_font in the bucket config".to_owned(),
+        };
+        return Err(Error::from_str(err.status(), err.to_string()));
+    }
+
+    let format = format.or(default_screenshot_task_params.format).unwrap();
+    let quality = quality.unwrap_or(default_screenshot_task_params.quality.unwrap());
+
+    let device = Device::resolve(
+        bucket,
+        device
+            .as_deref()
+            .or(default_screenshot_task_params.device.as_deref()),
+        DeviceOverrides {
+            width: width.map(u32::from),
+            height: height.map(u32::from),
+            device_scale_factor: dpr.map(Dpr::ratio),
+            mobile,
+            user_agent,
+        },
+        Device {
+            width: default_screenshot_task_params.width.unwrap().into(),
+            height: default_screenshot_task_params.height.unwrap().into(),
+            device_scale_factor: default_screenshot_task_params.dpr.map_or(2.0, Dpr::ratio),
+            mobile: default_screenshot_task_params.mobile.unwrap_or(false),
+            touch: false,
+            user_agent: default_screenshot_task_params.user_agent.clone(),
+        },
+    )
+    .map_err(|err| Error::from_str(err.status(), err.to_string()))?;
+    let scale = Into::<f64>::into(
+        scale.unwrap_or(default_screenshot_task_params.scale.unwrap()),
+    ) / 10.0;
+    let max_output_height = default_screenshot_task_params
+        .max_output_height
+        .unwrap_or(DEFAULT_MAX_OUTPUT_HEIGHT);
+
+    let ads = match block_ads.or(default_screenshot_task_params.block_ads).unwrap_or(false) {
+        true => Some(AdFilter::get(bucket).ok_or_else(|| {
+            let err = WorkerError::InvalidParam {
+                name: "block_ads".to_owned(),
+                value: "true".to_owned(),
+            };
+            Error::from_str(err.status(), err.to_string())
+        })?),
+        false => None,
+    };
+
+    let (url, document) = document::resolve(
+        op,
+        &SERVER_CONFIG.buckets.get(bucket).unwrap().markdown_stylesheet,
+        url,
+        html,
+        markdown,
+        base_url,
+    )
+    .await
+    .map_err(|err| Error::from_str(err.status(), err.to_string()))?;
+    document::check_scoped(
+        &url,
+        document.is_some(),
+        &[
+            ("geolocation", geolocation.is_some()),
+            ("cookies", cookies.is_some()),
+        ],
+        &[
+            ("headers", headers.is_some()),
+            ("basic_auth", basic_auth.is_some()),
+        ],
+    )
+    .map_err(|err| Error::from_str(err.status(), err.to_string()))?;
+
+    let injection = Injection::resolve(
+        op,
+        &default_screenshot_task_params.css_files,
+        &default_screenshot_task_params.js_files,
+        css.or_else(|| default_screenshot_task_params.css.clone()),
+        js.or_else(|| default_screenshot_task_params.js.clone()),
+        hide_selectors.or_else(|| default_screenshot_task_params.hide_selectors.clone()),
+    )
+    .await
+    .map_err(|err| Error::from_str(err.status(), err.to_string()))?;
 
-    let _ = SCREENSHOT_TASK_CHANNEL
-        .0
-        .unbounded_send(ScreenshotTask {
+    SCREENSHOT_TASKS
+        .push(ScreenshotTask {
             0: tx,
             1: ScreenshotTaskInner {
+                document,
+                device: device.clone(),
+                media: Media {
+                    color_scheme: color_scheme.or(default_screenshot_task_params.color_scheme),
+                    media_type: media_type.or(default_screenshot_task_params.media_type),
+                    reduced_motion: reduced_motion
+                        .or(default_screenshot_task_params.reduced_motion),
+                },
+                locale: Locale {
+                    timezone: timezone.or_else(|| default_screenshot_task_params.timezone.clone()),
+                    locale: locale.or_else(|| default_screenshot_task_params.locale.clone()),
+                    accept_language: accept_language
+                        .or_else(|| default_screenshot_task_params.accept_language.clone()),
+                    geolocation: geolocation
+                        .or_else(|| default_screenshot_task_params.geolocation.clone()),
+                },
+                overrides: RequestOverrides {
+                    headers: merged(&default_screenshot_task_params.headers, headers),
+                    cookies: merged(&default_screenshot_task_params.cookies, cookies),
+                    basic_auth: basic_auth
+                        .or_else(|| default_screenshot_task_params.basic_auth.clone()),
+                    blocking: Blocking {
+                        resources: block_resources
+                            .or_else(|| default_screenshot_task_params.block_resources.clone())
+                            .unwrap_or_default(),
+                        urls: block_urls
+                            .or_else(|| default_screenshot_task_params.block_urls.clone())
+                            .unwrap_or_default(),
+                        ads,
+                        allow_urls,
+                    },
+                },
+                injection,
+                cookie_banners: hide_cookie_banners
+                    .or(default_screenshot_task_params.hide_cookie_banners)
+                    .unwrap_or(false)
+                    .then(|| CookieBannerRules::for_bucket(bucket)),
+                actions,
                 full_page,
                 omit_background,
+                scroll_page: scroll_page
+                    .or(default_screenshot_task_params.scroll_page)
+                    .unwrap_or(false)
+                    .then(|| {
+                        // there's no use loading more than the stitched capture holds,
+                        // it is counted in output pixels and the scroll height in css ones
+                        let ratio = scale * device.device_scale_factor;
+                        scroll_max_height
+                            .or(default_screenshot_task_params.scroll_max_height)
+                            .unwrap_or(DEFAULT_SCROLL_MAX_HEIGHT)
+                            .min((f64::from(max_output_height) / ratio).ceil() as u32)
+                    }),
+                selector,
+                padding: padding
+                    .or(default_screenshot_task_params.padding)
+                    .unwrap_or(0),
+                clip_x,
+                clip_y,
+                clip_width,
+                clip_height,
+                scroll_to,
+                transform,
+                output_sizes,
+                viewports,
+                composite,
+                encoding: Encoding {
+                    format,
+                    quality: quality.min(100) as u8,
+                    optimize_png: optimize_png
+                        .or(default_screenshot_task_params.optimize_png)
+                        .unwrap_or(false),
+                },
+                max_output_height,
+                wait_until: wait_until
+                    .or_else(|| default_screenshot_task_params.wait_until.clone())
+                    .unwrap_or_default(),
+                wait_timeout,
                 bucket: bucket.to_owned(),
                 filename,
             },
             2: NavigateParams {
-                url: url.to_string(),
-                referrer: None,
+                url,
+                referrer: referrer.or_else(|| default_screenshot_task_params.referrer.clone()),
                 transition_type: None,
                 frame_id: None,
                 referrer_policy: None,
             },
             3: CaptureScreenshotParams {
-                format: Some(
-                    format.unwrap_or(default_screenshot_task_params.format.clone().unwrap()),
-                ),
-                quality: Some(
-                    quality
-                        .unwrap_or(default_screenshot_task_params.quality.unwrap())
-                        .into(),
-                ),
+                format: Some(format.capture_format()),
+                quality: Some(quality.into()),
                 clip: Some(Viewport {
                     x: 0.0,
                     y: 0.0,
-                    width: width
-                        .unwrap_or(default_screenshot_task_params.width.unwrap())
-                        .into(),
-                    height: height
-                        .unwrap_or(default_screenshot_task_params.height.unwrap())
-                        .into(),
-                    scale: Into::<f64>::into(
-                        scale.unwrap_or(default_screenshot_task_params.scale.unwrap()),
-                    ) / 10.0,
+                    width: device.width.into(),
+                    height: device.height.into(),
+                    scale,
                 }),
                 from_surface: None,
                 capture_beyond_viewport: None,
             },
-        })
-        .unwrap();
-
-    if let Ok(Some(filename)) = rx.await {
-        info!("redirect to {:#}", filename);
-        return Ok(Redirect::new(filename).into());
-    }
+        });
 
-    Err(Error::from_str(StatusCode::InternalServerError, ""))
+    pool::reply(rx).await
 }
 
 struct ScreenshotTaskInner {
     bucket: String,
     filename: String,
+    /// loaded in place of the navigation url, see `document::resolve`
+    document: Option<String>,
+    device: Device,
+    media: Media,
+    locale: Locale,
+    overrides: RequestOverrides,
+    injection: Injection,
+    cookie_banners: Option<CookieBannerRules>,
+    actions: Vec<ActionStep>,
     full_page: Option<bool>,
     omit_background: Option<bool>,
+    /// max height to scroll through, if at all
+    scroll_page: Option<u32>,
+    selector: Option<String>,
+    padding: u16,
+    clip_x: Option<u32>,
+    clip_y: Option<u32>,
+    clip_width: Option<u32>,
+    clip_height: Option<u32>,
+    scroll_to: Option<ScrollTo>,
+    transform: Transform,
+    /// widths of the variants, a single capture if empty
+    output_sizes: Vec<u32>,
+    /// labels and devices to capture the loaded page on, in place of `device`
+    viewports: Vec<(String, Device)>,
+    /// contact sheet of the `viewports`
+    composite: bool,
+    encoding: Encoding,
+    /// pixels, full page captures are cut off below
+    max_output_height: u32,
+    wait_until: WaitUntil,
+    wait_timeout: u64,
 }
 
 struct ScreenshotTask(
-    OneshotSender<Option<String>>,
+    OneshotSender<Result<TaskOutput, WorkerError>>,
     ScreenshotTaskInner,
     NavigateParams,
     CaptureScreenshotParams,
 );
 
+use std::collections::BTreeMap;
 use std::hash::Hash;
 
+use opendal::Operator;
+
 use crate::config::{DAL_OP_MAP, SERVER_CONFIG};
+use crate::error::WorkerError;
 use crate::util::hash::{calculate_hash, calculate_hash_str};
 use crate::util::signature_v4::{signed_url};
+use crate::worker::actions::{self, ActionStep};
+use crate::worker::block::{Blocking, ResourceKind};
+use crate::worker::consent::CookieBannerRules;
+use crate::worker::contact_sheet;
+use crate::worker::document::{self, Content};
+use crate::worker::element::element_box;
+use crate::worker::emulation::{
+    self, ColorScheme, Device, DeviceOverrides, Dpr, Geolocation, Locale, Media, MediaType,
+    ViewportSpec,
+};
+use crate::worker::filter_list::AdFilter;
+use crate::worker::inject::{self, Injection};
+use crate::worker::network::{self, merged, RequestOverrides};
+use crate::worker::output::{Manifest, StoredVariant, TaskOutput};
+use crate::worker::pool::{self, TaskQueue};
+use crate::worker::scroll::{scroll_through, ScrollTo, DEFAULT_SCROLL_MAX_HEIGHT};
+use crate::worker::stitch::{self, DEFAULT_MAX_OUTPUT_HEIGHT, MAX_TILE_HEIGHT};
+use crate::worker::transform::{self, Capture, Crop, Encoding, Fit, OutputFormat, Transform};
+use crate::worker::wait::{self, navigate, WaitUntil, DEFAULT_WAIT_TIMEOUT};
+use crate::worker::watermark::Watermark;
 
+/// Most `viewports` one request may ask for.
+const MAX_VIEWPORTS: usize = 8;
+
+/// Query string of a GET, or the JSON body of a POST request.
 #[derive(Debug, Serialize, Deserialize, Clone, Hash)]
 pub struct ScreenshotRequestQSParams {
-    pub url: Url,
+    /// exactly one of `url`, `html` and `markdown` is required
+    pub url: Option<Url>,
+    /// document to render, or the body of a `text/html` POST
+    pub html: Option<String>,
+    /// rendered with the bucket `markdown_stylesheet`, or the body of a `text/markdown` POST
+    pub markdown: Option<String>,
+    /// relative urls in `html` and `markdown` resolve against it, `headers` and
+    /// `basic_auth` go to its origin, `cookies` and `geolocation` aren't taken
+    pub base_url: Option<Url>,
 
-    pub format: Option<CaptureScreenshotFormat>,
+    /// `png`, `jpeg`, `webp` or `avif`
+    pub format: Option<OutputFormat>,
+    /// jpeg and avif, 0-100
     pub quality: Option<u16>,
+    /// lossless recompression of png output, slower
+    pub optimize_png: Option<bool>,
     pub width: Option<u16>,
     pub height: Option<u16>,
     pub scale: Option<u8>,
     pub ttl: Option<u64>,
 
+    /// preset name, see `emulation::Device::lookup`
+    pub device: Option<String>,
+    /// device pixel ratio, see `emulation::Dpr`
+    pub dpr: Option<Dpr>,
+    pub mobile: Option<bool>,
+    pub user_agent: Option<String>,
+
+    pub color_scheme: Option<ColorScheme>,
+    pub media_type: Option<MediaType>,
+    pub reduced_motion: Option<bool>,
+
+    /// IANA timezone id, e.g. `Europe/Berlin`
+    pub timezone: Option<String>,
+    /// BCP 47 tag, e.g. `de-DE`
+    pub locale: Option<String>,
+    /// defaults to `locale`
+    pub accept_language: Option<String>,
+    /// `lat,lon[,accuracy]`
+    pub geolocation: Option<Geolocation>,
+
+    /// sent to the origin of `url` only, e.g. `headers[X-Token]=...`
+    pub headers: Option<BTreeMap<String, String>>,
+    pub cookies: Option<BTreeMap<String, String>>,
+    pub referrer: Option<String>,
+    /// `user:password`
+    pub basic_auth: Option<String>,
+
+    /// `image`, `media`, `font`, `stylesheet`, `script` or `xhr`
+    pub block_resources: Option<Vec<ResourceKind>>,
+    /// url globs, `*` and `?` wildcards
+    pub block_urls: Option<Vec<String>>,
+    /// url globs, requests matching none of them are blocked
+    pub allow_urls: Option<Vec<String>>,
+    /// block requests matched by the bucket `filter_lists`
+    pub block_ads: Option<bool>,
+
+    /// injected after load, before `wait_until` is done
+    pub css: Option<String>,
+    pub js: Option<String>,
+    /// css selector list of elements to hide
+    pub hide_selectors: Option<String>,
+    /// accept and hide consent dialogs, see `consent::CookieBannerRules`
+    pub hide_cookie_banners: Option<bool>,
+
+    /// steps to run before capture, JSON body only
+    #[serde(default)]
+    pub actions: Vec<ActionStep>,
+
     pub full_page: Option<bool>,
     pub omit_background: Option<bool>,
+    /// scroll through the page before capture to load lazy content
+    pub scroll_page: Option<bool>,
+    /// css pixels, `DEFAULT_SCROLL_MAX_HEIGHT` unless set
+    pub scroll_max_height: Option<u32>,
+
+    /// capture only the first element matching this css selector
+    pub selector: Option<String>,
+    /// css pixels around `selector`
+    pub padding: Option<u16>,
+
+    /// clip rectangle relative to the `scroll_to` position,
+    /// `clip_width` and `clip_height` default to the viewport size
+    pub clip_x: Option<u32>,
+    pub clip_y: Option<u32>,
+    pub clip_width: Option<u32>,
+    pub clip_height: Option<u32>,
+    /// `<y>`, `<x>,<y>` or `#anchor`
+    pub scroll_to: Option<ScrollTo>,
+
+    /// pixels of the stored image, a single one keeps the aspect ratio
+    pub thumb_width: Option<u32>,
+    pub thumb_height: Option<u32>,
+    /// `x,y,width,height` in pixels of the capture, applied before resizing
+    pub crop: Option<Crop>,
+    /// `contain` unless set
+    pub fit: Option<Fit>,
+    /// widths in pixels, stores one resized copy each from a single capture
+    /// and answers with a JSON list of them instead of a redirect
+    pub output_sizes: Option<Vec<u32>>,
+    /// device presets or `<width>x<height>[@<dpr>]`, the page is loaded once
+    /// and captured on each, answered like `output_sizes`
+    pub viewports: Option<Vec<ViewportSpec>>,
+    /// also store the `viewports` captures side by side, labelled with the
+    /// bucket `label_font`
+    pub composite: Option<bool>,
+    /// turn the bucket `watermark` on or off, if it is `overridable`
+    pub watermark: Option<bool>,
+
+    pub wait_until: Option<WaitUntil>,
+    /// milliseconds
+    pub wait_timeout: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Hash)]
 pub struct ScreenshotRequestParams {
     #[serde(default = "default_format")]
-    pub format: Option<CaptureScreenshotFormat>,
+    pub format: Option<OutputFormat>,
     #[serde(default = "default_quality")]
     pub quality: Option<u16>,
+    pub optimize_png: Option<bool>,
     #[serde(default = "default_width")]
     pub width: Option<u16>,
     #[serde(default = "default_height")]
@@ -273,27 +923,88 @@ pub struct ScreenshotRequestParams {
     #[serde(default = "default_ttl")]
     pub ttl: Option<u64>,
 
+    pub device: Option<String>,
+    pub dpr: Option<Dpr>,
+    pub mobile: Option<bool>,
+    pub user_agent: Option<String>,
+
+    pub color_scheme: Option<ColorScheme>,
+    pub media_type: Option<MediaType>,
+    pub reduced_motion: Option<bool>,
+
+    /// IANA timezone id, e.g. `Europe/Berlin`
+    pub timezone: Option<String>,
+    /// BCP 47 tag, e.g. `de-DE`
+    pub locale: Option<String>,
+    /// defaults to `locale`
+    pub accept_language: Option<String>,
+    /// `lat,lon[,accuracy]`
+    pub geolocation: Option<Geolocation>,
+
+    /// merged with the request ones, the request wins
+    pub headers: Option<BTreeMap<String, String>>,
+    pub cookies: Option<BTreeMap<String, String>>,
+    pub referrer: Option<String>,
+    /// `user:password`
+    pub basic_auth: Option<String>,
+
+    /// `image`, `media`, `font`, `stylesheet`, `script` or `xhr`
+    pub block_resources: Option<Vec<ResourceKind>>,
+    /// url globs, `*` and `?` wildcards
+    pub block_urls: Option<Vec<String>>,
+    /// block requests matched by the bucket `filter_lists`
+    pub block_ads: Option<bool>,
+
+    /// paths in the bucket storage, injected before `css` and `js`
+    pub css_files: Option<Vec<String>>,
+    pub js_files: Option<Vec<String>>,
+    pub css: Option<String>,
+    pub js: Option<String>,
+    pub hide_selectors: Option<String>,
+    pub hide_cookie_banners: Option<bool>,
+
     pub full_page: Option<bool>,
     pub omit_background: Option<bool>,
+    pub scroll_page: Option<bool>,
+    pub scroll_max_height: Option<u32>,
+
+    pub padding: Option<u16>,
+
+    /// full page captures taller than this many pixels are truncated
+    #[serde(default = "default_max_output_height")]
+    pub max_output_height: Option<u32>,
+
+    #[serde(default = "default_wait_until")]
+    pub wait_until: Option<WaitUntil>,
+    #[serde(default = "default_wait_timeout")]
+    pub wait_timeout: Option<u64>,
 }
 
 impl ScreenshotRequestQSParams {
-    pub fn filename(&self) -> String {
+    /// The applied `watermark` is part of the hash, so changing the bucket
+    /// settings or the mark files doesn't serve stale captures.
+    pub fn filename(&self, watermark: Option<&Watermark>) -> String {
         format!(
             "{:#}/{:x}",
-            calculate_hash_str(&self.url.origin().ascii_serialization()),
-            calculate_hash(self)
+            calculate_hash_str(&document::origin(&self.url, &self.base_url)),
+            calculate_hash(&(
+                self,
+                watermark.map(|watermark| (&watermark.config, &watermark.digest))
+            ))
         )
     }
 
-    pub fn path(&self) -> String {
-        format!(
-            "{:#}.{:#}",
-            self.filename(),
-            self.format.clone()
-                .unwrap_or(CaptureScreenshotFormat::Jpeg)
-                .as_ref()
-        )
+    /// Where the capture, or the manifest of its `output_sizes` or
+    /// `viewports`, is stored. `default_format` is the bucket one.
+    pub fn path(&self, default_format: OutputFormat, watermark: Option<&Watermark>) -> String {
+        match self.output_sizes.is_some() || self.viewports.is_some() {
+            true => format!("{:#}.json", self.filename(watermark)),
+            false => format!(
+                "{:#}.{:#}",
+                self.filename(watermark),
+                self.format.unwrap_or(default_format).extension()
+            ),
+        }
     }
 }
 
@@ -301,17 +1012,48 @@ pub fn default_buckets_screenshot_task_params() -> Option<ScreenshotRequestParam
     Some(ScreenshotRequestParams {
         format: default_format(),
         quality: default_quality(),
+        optimize_png: None,
         width: default_width(),
         height: default_height(),
         scale: default_scale(),
         ttl: default_ttl(),
+        device: None,
+        dpr: None,
+        mobile: None,
+        user_agent: None,
+        color_scheme: None,
+        media_type: None,
+        reduced_motion: None,
+        timezone: None,
+        locale: None,
+        accept_language: None,
+        geolocation: None,
+        headers: None,
+        cookies: None,
+        referrer: None,
+        basic_auth: None,
+        block_resources: None,
+        block_urls: None,
+        block_ads: None,
+        css_files: None,
+        js_files: None,
+        css: None,
+        js: None,
+        hide_selectors: None,
+        hide_cookie_banners: None,
         full_page: None,
         omit_background: None,
+        scroll_page: None,
+        scroll_max_height: None,
+        padding: None,
+        max_output_height: default_max_output_height(),
+        wait_until: default_wait_until(),
+        wait_timeout: default_wait_timeout(),
     })
 }
 
-fn default_format() -> Option<CaptureScreenshotFormat> {
-    Some(CaptureScreenshotFormat::Jpeg)
+fn default_format() -> Option<OutputFormat> {
+    Some(OutputFormat::Jpeg)
 }
 
 fn default_quality() -> Option<u16> {
@@ -333,3 +1075,15 @@ fn default_scale() -> Option<u8> {
 fn default_ttl() -> Option<u64> {
     Some(60)
 }
+
+fn default_wait_until() -> Option<WaitUntil> {
+    Some(WaitUntil::Load)
+}
+
+fn default_wait_timeout() -> Option<u64> {
+    Some(DEFAULT_WAIT_TIMEOUT)
+}
+
+fn default_max_output_height() -> Option<u32> {
+    Some(DEFAULT_MAX_OUTPUT_HEIGHT)
+}
\ No newline at end of file
@@ -0,0 +1,198 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chromiumoxide::error::CdpError;
+use chromiumoxide::Page;
+use tokio::time::{sleep, timeout};
+
+use crate::error::WorkerError;
+use crate::worker::wait::NetworkIdle;
+
+/// Default cap of `scroll_page`, in css pixels.
+pub const DEFAULT_SCROLL_MAX_HEIGHT: u32 = 20_000;
+
+/// Longest time spent scrolling, the steps are paced and pages may keep
+/// growing while we go.
+const SCROLL_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Pause after every step, gives observers a frame to fire and start their requests.
+const SCROLL_STEP_DELAY: Duration = Duration::from_millis(100);
+
+/// Scroll position to move the window to before capture.
+///
+/// Parsed from the `scroll_to` query parameter: `<y>`, `<x>,<y>` in css
+/// pixels, or `#<id>` to scroll an anchor to the top of the viewport.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ScrollTo {
+    Offset(u32, u32),
+    Anchor(String),
+}
+
+impl ScrollTo {
+    /// Scrolls and returns the resulting `(scrollX, scrollY)`, which may be
+    /// smaller than requested near the end of the document.
+    pub async fn apply(&self, page: &Page) -> Result<(f64, f64), WorkerError> {
+        let expression = match self {
+            ScrollTo::Offset(x, y) => format!(
+                "(() => {{ window.scrollTo({}, {}); return [window.scrollX, window.scrollY]; }})()",
+                x, y
+            ),
+            ScrollTo::Anchor(id) => format!(
+                r#"((id) => {{
+                    const anchor = document.getElementById(id) || document.getElementsByName(id)[0];
+                    if (!anchor) return null;
+                    anchor.scrollIntoView({{ block: 'start', inline: 'start' }});
+                    return [window.scrollX, window.scrollY];
+                }})({})"#,
+                serde_json::to_string(id).unwrap()
+            ),
+        };
+        page.evaluate_expression(expression)
+            .await?
+            .into_value::<(f64, f64)>()
+            .map_err(|_| WorkerError::SelectorNotFound(self.to_string()))
+    }
+}
+
+impl FromStr for ScrollTo {
+    type Err = WorkerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || WorkerError::InvalidParam {
+            name: "scroll_to".to_owned(),
+            value: s.to_owned(),
+        };
+        if let Some(id) = s.strip_prefix('#') {
+            return match id.is_empty() {
+                true => Err(invalid()),
+                false => Ok(ScrollTo::Anchor(id.to_owned())),
+            };
+        }
+        match s.split_once(',') {
+            Some((x, y)) => Ok(ScrollTo::Offset(
+                x.trim().parse().map_err(|_| invalid())?,
+                y.trim().parse().map_err(|_| invalid())?,
+            )),
+            None => Ok(ScrollTo::Offset(
+                0,
+                s.trim().parse().map_err(|_| invalid())?,
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for ScrollTo {
+    type Error = WorkerError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ScrollTo> for String {
+    fn from(value: ScrollTo) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for ScrollTo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScrollTo::Offset(x, y) => write!(f, "{},{}", x, y),
+            ScrollTo::Anchor(id) => write!(f, "#{}", id),
+        }
+    }
+}
+
+/// Scrolls down a viewport at a time until the end of the document or
+/// `max_height` css pixels, so lazy images and `IntersectionObserver`
+/// content start loading, then returns to the top. Scrolling stops early
+/// after `SCROLL_TIMEOUT`.
+///
+/// Waits up to `idle_timeout` milliseconds for the network to go idle after
+/// the last step; pages that never settle are captured as they are then.
+pub async fn scroll_through(
+    page: &Page,
+    max_height: u32,
+    idle_timeout: u64,
+) -> Result<(), WorkerError> {
+    let mut network_idle = NetworkIdle::listen(page).await?;
+
+    let scrolled = timeout(SCROLL_TIMEOUT, async {
+        let mut y = 0;
+        loop {
+            // re-read every step, infinite scroll pages grow while we go
+            let (viewport_height, document_height) = page
+                .evaluate_expression(format!(
+                    "(() => {{ window.scrollTo(0, {}); return [window.innerHeight, document.documentElement.scrollHeight]; }})()",
+                    y
+                ))
+                .await?
+                .into_value::<(u32, u32)>()
+                .map_err(CdpError::from)?;
+            sleep(SCROLL_STEP_DELAY).await;
+
+            y += viewport_height.max(1);
+            if y >= document_height.min(max_height) {
+                return Ok::<(), WorkerError>(());
+            }
+        }
+    })
+    .await;
+    // out of time, what loaded so far is captured
+    if let Ok(result) = scrolled {
+        result?;
+    }
+
+    let _ = timeout(Duration::from_millis(idle_timeout), network_idle.wait(0)).await;
+
+    ScrollTo::Offset(0, 0).apply(page).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_offsets_and_anchors() {
+        let parsed = |s: &str| s.parse::<ScrollTo>().unwrap();
+        assert_eq!(parsed("300"), ScrollTo::Offset(0, 300));
+        assert_eq!(parsed("0"), ScrollTo::Offset(0, 0));
+        assert_eq!(parsed("10,300"), ScrollTo::Offset(10, 300));
+        assert_eq!(parsed(" 10 , 300 "), ScrollTo::Offset(10, 300));
+        assert_eq!(parsed("#pricing"), ScrollTo::Anchor("pricing".to_owned()));
+        // ids are taken as they are, only the leading `#` is stripped
+        assert_eq!(parsed("##a,b"), ScrollTo::Anchor("#a,b".to_owned()));
+    }
+
+    #[test]
+    fn rejects_what_is_neither() {
+        assert!("".parse::<ScrollTo>().is_err());
+        assert!("#".parse::<ScrollTo>().is_err());
+        assert!("-5".parse::<ScrollTo>().is_err());
+        assert!("1.5".parse::<ScrollTo>().is_err());
+        assert!("0x10".parse::<ScrollTo>().is_err());
+        assert!("top".parse::<ScrollTo>().is_err());
+        assert!("1,".parse::<ScrollTo>().is_err());
+        assert!("1,2,3".parse::<ScrollTo>().is_err());
+        match ",1".parse::<ScrollTo>() {
+            Err(WorkerError::InvalidParam { name, value }) => {
+                assert_eq!((name.as_str(), value.as_str()), ("scroll_to", ",1"))
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn displays_offsets_in_full() {
+        assert_eq!(ScrollTo::Offset(0, 300).to_string(), "0,300");
+        assert_eq!("300".parse::<ScrollTo>().unwrap().to_string(), "0,300");
+        assert_eq!(
+            "#pricing".parse::<ScrollTo>().unwrap().to_string(),
+            "#pricing"
+        );
+    }
+}
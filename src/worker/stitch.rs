@@ -0,0 +1,86 @@
+use chromiumoxide::{page::ScreenshotParams, Page};
+use chromiumoxide_cdp::cdp::browser_protocol::page::{
+    CaptureScreenshotFormat, CaptureScreenshotParams, Viewport,
+};
+use image::{imageops, ImageFormat, RgbaImage};
+
+use crate::error::WorkerError;
+
+/// Tallest single capture in pixels. Chrome's limit is around 16k,
+/// captures beyond it come out blank or corrupt.
+pub const MAX_TILE_HEIGHT: u32 = 8_192;
+
+/// Default cap of the full page output height in pixels, see `max_output_height`.
+pub const DEFAULT_MAX_OUTPUT_HEIGHT: u32 = 32_768;
+
+/// Output of a tiled full page capture.
+pub struct Stitched {
+    pub image: RgbaImage,
+    /// the document was taller than the output height cap or wider than
+    /// the viewport
+    pub truncated: bool,
+}
+
+/// Size of the document in css pixels.
+pub async fn document_size(page: &Page) -> Result<(f64, f64), WorkerError> {
+    let metrics = page.layout_metrics().await?;
+    Ok((
+        metrics.css_content_size.width,
+        metrics.css_content_size.height,
+    ))
+}
+
+/// Captures the document in tiles of at most `MAX_TILE_HEIGHT` pixels and
+/// stitches them into one image, cut off at `max_height` pixels and at
+/// `viewport_width` css pixels.
+///
+/// Output pixels per css pixel are `scale` times `device_scale_factor`.
+pub async fn capture(
+    page: &Page,
+    (width, height): (f64, f64),
+    viewport_width: f64,
+    scale: f64,
+    device_scale_factor: f64,
+    max_height: u32,
+    omit_background: Option<bool>,
+) -> Result<Stitched, WorkerError> {
+    let ratio = scale * device_scale_factor;
+    let css_width = width.min(viewport_width);
+    let full_height = (height * ratio).ceil() as u32;
+    let output_height = full_height.min(max_height);
+    let css_height = f64::from(output_height) / ratio;
+    let tile_css_height = (f64::from(MAX_TILE_HEIGHT) / ratio).floor();
+
+    let mut canvas = RgbaImage::new((css_width * ratio).ceil() as u32, output_height);
+    let mut y = 0.0;
+    while y < css_height {
+        let tile = page
+            .screenshot(ScreenshotParams {
+                cdp_params: CaptureScreenshotParams {
+                    // lossless tiles, the output is encoded once at the end
+                    format: Some(CaptureScreenshotFormat::Png),
+                    quality: None,
+                    clip: Some(Viewport {
+                        x: 0.0,
+                        y,
+                        width: css_width,
+                        height: tile_css_height.min(css_height - y),
+                        scale,
+                    }),
+                    from_surface: None,
+                    capture_beyond_viewport: Some(true),
+                },
+                full_page: None,
+                omit_background,
+            })
+            .await?;
+        let tile = image::load_from_memory_with_format(&tile, ImageFormat::Png)?.to_rgba8();
+        imageops::replace(&mut canvas, &tile, 0, (y * ratio).round() as i64);
+        y += tile_css_height;
+    }
+
+    Ok(Stitched {
+        image: canvas,
+        truncated: output_height < full_height || css_width < width,
+    })
+}
@@ -0,0 +1,92 @@
+use handlebars::Handlebars;
+use opendal::ErrorKind;
+use tide::{Error, Request};
+
+use crate::config::{DAL_OP_MAP, SERVER_CONFIG};
+use crate::error::WorkerError;
+use crate::worker::pdf::{self, PDFRequestQSParams};
+use crate::worker::screenshot::{self, ScreenshotRequestQSParams};
+use crate::worker::transform::OutputFormat;
+
+/// Query string of a `/render` request, the JSON body is the template data.
+#[derive(Debug, Deserialize)]
+struct RenderQSParams {
+    /// `pdf`, or an image format of the screenshot endpoint, `png` unless set
+    format: Option<String>,
+}
+
+/// Fills `name` from the bucket `template_dir` with `data`.
+///
+/// Templates are handlebars, `{{value}}` is html escaped and `{{{value}}}` is not.
+/// The filled in document is part of the cache key of the capture, a new
+/// version of the template or other data don't hit the old one.
+pub async fn fill(
+    bucket: &str,
+    name: &str,
+    data: &serde_json::Value,
+) -> Result<String, WorkerError> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(WorkerError::InvalidParam {
+            name: "template".to_owned(),
+            value: name.to_owned(),
+        });
+    }
+    let path = format!(
+        "{}{}.html",
+        SERVER_CONFIG.buckets.get(bucket).unwrap().template_dir,
+        name
+    );
+    let source = match DAL_OP_MAP.get(bucket).unwrap().read(&path).await {
+        Ok(source) => String::from_utf8_lossy(&source).into_owned(),
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            return Err(WorkerError::TemplateNotFound(name.to_owned()))
+        }
+        Err(err) => return Err(err.into()),
+    };
+    Ok(Handlebars::new().render_template(&source, data)?)
+}
+
+/// `POST /render/{bucket}/{template}`, renders the filled in template as a
+/// screenshot or pdf. Everything but `format` in the query string is passed
+/// on like to those endpoints.
+pub async fn render(mut req: Request<()>, bucket: &str) -> tide::Result {
+    let name = req.param("template")?.to_owned();
+    let RenderQSParams { format } = req.query()?;
+    let data: serde_json::Value = req.body_json().await?;
+    let html = fill(bucket, &name, &data)
+        .await
+        .map_err(|err| Error::from_str(err.status(), err.to_string()))?;
+
+    match format.as_deref() {
+        Some("pdf") => {
+            pdf::enqueue(
+                PDFRequestQSParams {
+                    url: None,
+                    html: Some(html),
+                    markdown: None,
+                    ..req.query()?
+                },
+                bucket,
+            )
+            .await
+        }
+        _ => {
+            let params: ScreenshotRequestQSParams = req.query()?;
+            screenshot::enqueue(
+                ScreenshotRequestQSParams {
+                    url: None,
+                    html: Some(html),
+                    markdown: None,
+                    format: params.format.or(Some(OutputFormat::Png)),
+                    ..params
+                },
+                bucket,
+            )
+            .await
+        }
+    }
+}
@@ -0,0 +1,434 @@
+use std::io::Cursor;
+
+use chromiumoxide_cdp::cdp::browser_protocol::page::CaptureScreenshotFormat;
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageError, ImageReader, RgbaImage};
+
+use crate::error::WorkerError;
+use crate::util::param::string_param;
+use crate::worker::watermark::Watermark;
+
+/// 1 (slowest, smallest) to 10 (fastest), screenshots favour speed.
+const AVIF_SPEED: u8 = 8;
+
+/// Largest `thumb_width` or `thumb_height` in pixels. Resizes never grow an
+/// image past it, the side that follows the aspect ratio included.
+pub const MAX_RESIZE: u32 = 8_192;
+
+/// Most `output_sizes` one request may ask for, each is a resize and an encode.
+pub const MAX_OUTPUT_SIZES: usize = 8;
+
+/// How thumbnails with both `thumb_width` and `thumb_height` are sized.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// fill the box exactly, cutting off what sticks out
+    Cover,
+    /// fit into the box, keeping all of the image
+    #[default]
+    Contain,
+}
+
+string_param! {
+    /// Rectangle of the capture in pixels, as `x,y,width,height`.
+    pub struct Crop, "crop", |s| Crop::parse(s).is_some()
+}
+
+impl Crop {
+    fn parse(s: &str) -> Option<(u32, u32, u32, u32)> {
+        let parts = s
+            .split(',')
+            .map(|part| part.trim().parse::<u32>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        match parts[..] {
+            [x, y, width, height] if width > 0 && height > 0 => Some((x, y, width, height)),
+            _ => None,
+        }
+    }
+
+    /// (x, y, width, height)
+    pub fn rect(&self) -> (u32, u32, u32, u32) {
+        Self::parse(&self.0).unwrap()
+    }
+}
+
+/// Crop, resize, then watermark, applied to the captured image before it is stored.
+#[derive(Debug, Clone, Default)]
+pub struct Transform {
+    pub crop: Option<Crop>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fit: Fit,
+    pub watermark: Option<&'static Watermark>,
+}
+
+impl Transform {
+    pub fn is_empty(&self) -> bool {
+        self.crop.is_none()
+            && self.width.is_none()
+            && self.height.is_none()
+            && self.watermark.is_none()
+    }
+
+    /// `width` and `height` have to be between 1 and `MAX_RESIZE`.
+    pub fn validate(&self) -> Result<(), WorkerError> {
+        for (name, value) in [("thumb_width", self.width), ("thumb_height", self.height)] {
+            if let Some(value) = value.filter(|value| !(1..=MAX_RESIZE).contains(value)) {
+                return Err(WorkerError::InvalidParam {
+                    name: name.to_owned(),
+                    value: value.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// A single thumbnail dimension keeps the aspect ratio.
+    pub fn apply(&self, mut image: DynamicImage) -> Result<DynamicImage, WorkerError> {
+        if let Some(crop) = &self.crop {
+            let (x, y, width, height) = crop.rect();
+            // clamped to the image, a crop entirely outside leaves nothing
+            image = image.crop_imm(x, y, width, height);
+            if image.width() == 0 || image.height() == 0 {
+                return Err(WorkerError::InvalidParam {
+                    name: "crop".to_owned(),
+                    value: crop.0.clone(),
+                });
+            }
+        }
+        let image = match (self.width, self.height, self.fit) {
+            (None, None, _) => image,
+            (Some(width), None, _) => {
+                image.resize(width, bound(image.height()), FilterType::Lanczos3)
+            }
+            (None, Some(height), _) => {
+                image.resize(bound(image.width()), height, FilterType::Lanczos3)
+            }
+            (Some(width), Some(height), Fit::Contain) => {
+                image.resize(width, height, FilterType::Lanczos3)
+            }
+            (Some(width), Some(height), Fit::Cover) => {
+                image.resize_to_fill(width, height, FilterType::Lanczos3)
+            }
+        };
+        Ok(watermarked(image, self.watermark))
+    }
+}
+
+/// Format of the stored screenshot. Chrome encodes png, jpeg and webp
+/// itself, avif is encoded here from a png capture.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Png,
+    #[serde(alias = "jpg")]
+    Jpeg,
+    Webp,
+    Avif,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Avif => "avif",
+        }
+    }
+
+    /// Format to request from `Page.captureScreenshot`.
+    pub fn capture_format(&self) -> CaptureScreenshotFormat {
+        match self {
+            OutputFormat::Jpeg => CaptureScreenshotFormat::Jpeg,
+            OutputFormat::Webp => CaptureScreenshotFormat::Webp,
+            OutputFormat::Png | OutputFormat::Avif => CaptureScreenshotFormat::Png,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Encoding {
+    pub format: OutputFormat,
+    /// 0-100, jpeg and avif
+    pub quality: u8,
+    /// lossless recompression of png output
+    pub optimize_png: bool,
+}
+
+/// A capture before it is turned into the stored file.
+pub enum Capture {
+    /// as returned by `Page.captureScreenshot`, in `OutputFormat::capture_format`
+    Encoded(Vec<u8>),
+    /// stitched from tiles
+    Decoded(RgbaImage),
+}
+
+impl Capture {
+    pub fn decode(self) -> Result<DynamicImage, WorkerError> {
+        Ok(match self {
+            Capture::Encoded(buf) => image::load_from_memory(&buf)?,
+            Capture::Decoded(image) => DynamicImage::ImageRgba8(image),
+        })
+    }
+}
+
+/// An encoded file ready to be stored.
+pub struct Rendered {
+    pub width: u32,
+    pub height: u32,
+    pub buf: Vec<u8>,
+}
+
+impl Rendered {
+    fn encode(image: DynamicImage, encoding: &Encoding) -> Result<Self, WorkerError> {
+        Ok(Rendered {
+            width: image.width(),
+            height: image.height(),
+            buf: optimize(encode(image, encoding)?, encoding),
+        })
+    }
+}
+
+/// Crops and resizes the capture, then encodes it unless Chrome's own
+/// encoding can be stored as is.
+pub fn finish(
+    capture: Capture,
+    transform: &Transform,
+    encoding: &Encoding,
+) -> Result<Rendered, WorkerError> {
+    match capture {
+        Capture::Encoded(buf) if transform.is_empty() && encoding.format != OutputFormat::Avif => {
+            // only the header is read for the size
+            let (width, height) = ImageReader::new(Cursor::new(&buf))
+                .with_guessed_format()
+                .map_err(ImageError::IoError)?
+                .into_dimensions()?;
+            Ok(Rendered {
+                width,
+                height,
+                buf: optimize(buf, encoding),
+            })
+        }
+        capture => Rendered::encode(transform.apply(capture.decode()?)?, encoding),
+    }
+}
+
+/// `widths` may have at most `MAX_OUTPUT_SIZES` entries between 1 and `MAX_RESIZE`.
+pub fn validate_sizes(widths: &[u32]) -> Result<(), WorkerError> {
+    if widths.is_empty()
+        || widths.len() > MAX_OUTPUT_SIZES
+        || widths.iter().any(|width| !(1..=MAX_RESIZE).contains(width))
+    {
+        return Err(WorkerError::InvalidParam {
+            name: "output_sizes".to_owned(),
+            value: format!("{:?}", widths),
+        });
+    }
+    Ok(())
+}
+
+/// Decodes the capture once, then crops, resizes to each of `widths` keeping
+/// the aspect ratio, watermarks and encodes. `transform` height and fit are
+/// not used.
+pub fn finish_sizes(
+    capture: Capture,
+    transform: &Transform,
+    widths: &[u32],
+    encoding: &Encoding,
+) -> Result<Vec<Rendered>, WorkerError> {
+    validate_sizes(widths)?;
+    let image = Transform {
+        crop: transform.crop.clone(),
+        ..Transform::default()
+    }
+    .apply(capture.decode()?)?;
+    widths
+        .iter()
+        .map(|&width| {
+            Rendered::encode(
+                watermarked(
+                    image.resize(width, bound(image.height()), FilterType::Lanczos3),
+                    transform.watermark,
+                ),
+                encoding,
+            )
+        })
+        .collect()
+}
+
+/// Limit for the side of a resize that follows the aspect ratio, it may
+/// keep its size but not grow past `MAX_RESIZE`.
+fn bound(size: u32) -> u32 {
+    size.max(MAX_RESIZE)
+}
+
+fn watermarked(image: DynamicImage, watermark: Option<&Watermark>) -> DynamicImage {
+    match watermark {
+        Some(watermark) => {
+            let mut image = image.to_rgba8();
+            watermark.apply(&mut image);
+            DynamicImage::ImageRgba8(image)
+        }
+        None => image,
+    }
+}
+
+fn encode(image: DynamicImage, encoding: &Encoding) -> Result<Vec<u8>, WorkerError> {
+    let mut buf = Cursor::new(vec![]);
+    match encoding.format {
+        OutputFormat::Jpeg => image
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(
+                &mut buf,
+                encoding.quality.clamp(1, 100),
+            ))?,
+        // the encoder only does lossless webp
+        OutputFormat::Webp => image
+            .to_rgba8()
+            .write_with_encoder(WebPEncoder::new_lossless(&mut buf))?,
+        OutputFormat::Avif => {
+            image
+                .to_rgba8()
+                .write_with_encoder(AvifEncoder::new_with_speed_quality(
+                    &mut buf,
+                    AVIF_SPEED,
+                    encoding.quality.clamp(1, 100),
+                ))?
+        }
+        OutputFormat::Png => image
+            .to_rgba8()
+            .write_with_encoder(PngEncoder::new(&mut buf))?,
+    }
+    Ok(buf.into_inner())
+}
+
+/// Keeps the input if oxipng can't make sense of it.
+fn optimize(buf: Vec<u8>, encoding: &Encoding) -> Vec<u8> {
+    match encoding.format == OutputFormat::Png && encoding.optimize_png {
+        true => oxipng::optimize_from_memory(&buf, &oxipng::Options::from_preset(2)).unwrap_or(buf),
+        false => buf,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resized(transform: Transform, width: u32, height: u32) -> (u32, u32) {
+        let image = transform
+            .apply(DynamicImage::new_rgba8(width, height))
+            .unwrap();
+        (image.width(), image.height())
+    }
+
+    #[test]
+    fn crop_keeps_the_string_as_given() {
+        let crop = Crop::try_from(" 10, 20 ,30 , 40 ".to_owned()).unwrap();
+        assert_eq!(crop.rect(), (10, 20, 30, 40));
+        assert_eq!(String::from(crop), " 10, 20 ,30 , 40 ");
+    }
+
+    #[test]
+    fn crop_takes_four_integers_and_a_size() {
+        for s in [
+            "",
+            "0,0,100",
+            "0,0,100,50,1",
+            "0,0,0,50",
+            "0,0,100,0",
+            "-1,0,100,50",
+            "0,0,1.5,50",
+            "a,b,c,d",
+            "0,,100,50",
+        ] {
+            assert!(
+                matches!(
+                    Crop::try_from(s.to_owned()),
+                    Err(WorkerError::InvalidParam { ref name, ref value }) if name == "crop" && value == s
+                ),
+                "{:?}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn crops_within_the_image() {
+        let cropped = |s: &str| Transform {
+            crop: Some(Crop::try_from(s.to_owned()).unwrap()),
+            ..Transform::default()
+        };
+        assert_eq!(resized(cropped("10,10,20,30"), 100, 100), (20, 30));
+        // clamped to the image
+        assert_eq!(resized(cropped("90,80,50,50"), 100, 100), (10, 20));
+        assert!(cropped("100,0,10,10")
+            .apply(DynamicImage::new_rgba8(100, 100))
+            .is_err());
+    }
+
+    #[test]
+    fn resizes_thumbnails() {
+        let thumb = |width, height, fit| Transform {
+            width,
+            height,
+            fit,
+            ..Transform::default()
+        };
+        assert_eq!(
+            resized(thumb(Some(50), None, Fit::Contain), 100, 200),
+            (50, 100)
+        );
+        assert_eq!(
+            resized(thumb(None, Some(50), Fit::Contain), 100, 200),
+            (25, 50)
+        );
+        assert_eq!(
+            resized(thumb(Some(40), Some(40), Fit::Contain), 100, 200),
+            (20, 40)
+        );
+        assert_eq!(
+            resized(thumb(Some(40), Some(40), Fit::Cover), 100, 200),
+            (40, 40)
+        );
+        // the free side doesn't grow past MAX_RESIZE
+        let (_, height) = resized(thumb(Some(MAX_RESIZE), None, Fit::Contain), 10, 1000);
+        assert_eq!(height, MAX_RESIZE);
+    }
+
+    #[test]
+    fn thumbnail_sizes_are_bounded() {
+        let thumb = |width, height| Transform {
+            width,
+            height,
+            ..Transform::default()
+        };
+        assert!(thumb(None, None).validate().is_ok());
+        assert!(thumb(Some(1), Some(MAX_RESIZE)).validate().is_ok());
+        assert!(thumb(Some(0), None).validate().is_err());
+        let err = thumb(None, Some(MAX_RESIZE + 1)).validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("invalid parameter \"thumb_height\" \"{}\"", MAX_RESIZE + 1)
+        );
+    }
+
+    #[test]
+    fn output_sizes_are_bounded() {
+        assert!(validate_sizes(&[320, 640, MAX_RESIZE]).is_ok());
+        assert!(validate_sizes(&[100; MAX_OUTPUT_SIZES]).is_ok());
+        let rejected = [
+            vec![],
+            vec![0],
+            vec![MAX_RESIZE + 1],
+            vec![100; MAX_OUTPUT_SIZES + 1],
+        ];
+        for widths in &rejected {
+            assert!(validate_sizes(widths).is_err(), "{:?}", widths);
+        }
+    }
+}
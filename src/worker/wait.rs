@@ -0,0 +1,386 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use chromiumoxide::listeners::EventStream;
+use chromiumoxide::Page;
+use chromiumoxide_cdp::cdp::browser_protocol::emulation::{
+    EventVirtualTimeBudgetExpired, SetVirtualTimePolicyParams, VirtualTimePolicy,
+};
+use chromiumoxide_cdp::cdp::browser_protocol::network::{
+    EventLoadingFailed, EventLoadingFinished, EventRequestWillBeSent, RequestId,
+};
+use chromiumoxide_cdp::cdp::browser_protocol::page::NavigateParams;
+use futures::stream::{select, BoxStream};
+use futures::StreamExt;
+use tokio::time::{sleep, timeout, timeout_at};
+
+use crate::error::WorkerError;
+use crate::worker::document;
+
+/// How long the network has to stay quiet before it counts as idle.
+/// Same window as puppeteer's `networkidle0` / `networkidle2`.
+const NETWORK_IDLE_WINDOW: Duration = Duration::from_millis(500);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Upper bound for navigation plus `wait_until`, in milliseconds.
+pub const DEFAULT_WAIT_TIMEOUT: u64 = 30_000;
+
+/// Largest `wait_timeout` a request may set, in milliseconds.
+pub const MAX_WAIT_TIMEOUT: u64 = 120_000;
+
+/// Readiness condition to wait for after navigation and before capture.
+///
+/// Parsed from the `wait_until` query parameter:
+/// `load`, `domcontentloaded`, `networkidle0`, `networkidle2`,
+/// `selector:<css>`, `function:<js expression>` or `delay:<ms>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(try_from = "String", into = "String")]
+pub enum WaitUntil {
+    #[default]
+    Load,
+    DomContentLoaded,
+    NetworkIdle0,
+    NetworkIdle2,
+    Selector(String),
+    Function(String),
+    Delay(u64),
+}
+
+impl FromStr for WaitUntil {
+    type Err = WorkerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || WorkerError::InvalidParam {
+            name: "wait_until".to_owned(),
+            value: s.to_owned(),
+        };
+        match s.split_once(':') {
+            Some(("selector", selector)) if !selector.is_empty() => {
+                Ok(WaitUntil::Selector(selector.to_owned()))
+            }
+            Some(("function", expression)) if !expression.is_empty() => {
+                Ok(WaitUntil::Function(expression.to_owned()))
+            }
+            Some(("delay", ms)) => ms.parse().map(WaitUntil::Delay).map_err(|_| invalid()),
+            Some(_) => Err(invalid()),
+            None => match s {
+                "load" => Ok(WaitUntil::Load),
+                "domcontentloaded" => Ok(WaitUntil::DomContentLoaded),
+                "networkidle0" => Ok(WaitUntil::NetworkIdle0),
+                "networkidle2" => Ok(WaitUntil::NetworkIdle2),
+                _ => Err(invalid()),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for WaitUntil {
+    type Error = WorkerError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<WaitUntil> for String {
+    fn from(value: WaitUntil) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for WaitUntil {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitUntil::Load => write!(f, "load"),
+            WaitUntil::DomContentLoaded => write!(f, "domcontentloaded"),
+            WaitUntil::NetworkIdle0 => write!(f, "networkidle0"),
+            WaitUntil::NetworkIdle2 => write!(f, "networkidle2"),
+            WaitUntil::Selector(selector) => write!(f, "selector:{}", selector),
+            WaitUntil::Function(expression) => write!(f, "function:{}", expression),
+            WaitUntil::Delay(ms) => write!(f, "delay:{}", ms),
+        }
+    }
+}
+
+enum Inflight {
+    Started(RequestId),
+    Done(RequestId),
+}
+
+/// Tracks in-flight requests of a page.
+///
+/// Has to be created before the navigation is issued, otherwise requests
+/// started during the navigation are never seen.
+pub struct NetworkIdle {
+    events: BoxStream<'static, Inflight>,
+    inflight: HashSet<RequestId>,
+}
+
+impl NetworkIdle {
+    pub async fn listen(page: &Page) -> Result<Self, WorkerError> {
+        let started = page
+            .event_listener::<EventRequestWillBeSent>()
+            .await?
+            .map(|event| Inflight::Started(event.request_id.clone()));
+        let finished = page
+            .event_listener::<EventLoadingFinished>()
+            .await?
+            .map(|event| Inflight::Done(event.request_id.clone()));
+        let failed = page
+            .event_listener::<EventLoadingFailed>()
+            .await?
+            .map(|event| Inflight::Done(event.request_id.clone()));
+
+        Ok(Self {
+            events: select(started, select(finished, failed)).boxed(),
+            inflight: HashSet::new(),
+        })
+    }
+
+    /// Resolves once at most `max_inflight` requests have been open for
+    /// `NETWORK_IDLE_WINDOW` in a row.
+    pub async fn wait(&mut self, max_inflight: usize) {
+        let mut idle_since = Some(Instant::now()).filter(|_| self.inflight.len() <= max_inflight);
+        loop {
+            let event = match idle_since {
+                Some(since) => {
+                    match timeout(
+                        NETWORK_IDLE_WINDOW.saturating_sub(since.elapsed()),
+                        self.events.next(),
+                    )
+                    .await
+                    {
+                        Ok(event) => event,
+                        Err(_) => return,
+                    }
+                }
+                None => self.events.next().await,
+            };
+            match event {
+                Some(Inflight::Started(id)) => {
+                    self.inflight.insert(id);
+                }
+                Some(Inflight::Done(id)) => {
+                    self.inflight.remove(&id);
+                }
+                None => return,
+            }
+            if self.inflight.len() <= max_inflight {
+                idle_since.get_or_insert_with(Instant::now);
+            } else {
+                idle_since = None;
+            }
+        }
+    }
+}
+
+/// Polls a js expression until it evaluates to a truthy value.
+/// Promises are awaited, exceptions count as not ready yet.
+pub async fn wait_for_function(page: &Page, expression: &str) {
+    let expression = format!("(async () => !!(await ({})))()", expression);
+    loop {
+        if let Ok(Ok(true)) = page
+            .evaluate_expression(expression.as_str())
+            .await
+            .map(|result| result.into_value::<bool>())
+        {
+            return;
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+pub async fn wait_for_selector(page: &Page, selector: &str) {
+    wait_for_function(
+        page,
+        &format!(
+            "document.querySelector({})",
+            serde_json::to_string(selector).unwrap()
+        ),
+    )
+    .await
+}
+
+async fn wait_for_ready_state(page: &Page, ready_state: &str) {
+    wait_for_function(
+        page,
+        &format!(
+            "document.readyState === 'complete' || document.readyState === {}",
+            serde_json::to_string(ready_state).unwrap()
+        ),
+    )
+    .await
+}
+
+/// Rejects a `wait_timeout` above `MAX_WAIT_TIMEOUT`, a worker is busy for that long.
+pub fn validate_timeout(wait_timeout: u64) -> Result<(), WorkerError> {
+    match wait_timeout > MAX_WAIT_TIMEOUT {
+        true => Err(WorkerError::InvalidParam {
+            name: "wait_timeout".to_owned(),
+            value: wait_timeout.to_string(),
+        }),
+        false => Ok(()),
+    }
+}
+
+/// Navigates `page` and waits for `wait_until`, all bounded by `wait_timeout` milliseconds.
+/// If a `document` is given it's put into the page in place of loading the url.
+///
+/// `after_load` runs once the navigation is done and before waiting starts,
+/// its output is returned.
+pub async fn navigate<T>(
+    page: &Page,
+    navigate_params: NavigateParams,
+    document: Option<&str>,
+    wait_until: &WaitUntil,
+    wait_timeout: u64,
+    after_load: impl Future<Output = Result<T, WorkerError>>,
+) -> Result<T, WorkerError> {
+    let mut network_idle = match wait_until {
+        WaitUntil::NetworkIdle0 | WaitUntil::NetworkIdle2 => Some(NetworkIdle::listen(page).await?),
+        _ => None,
+    };
+
+    timeout(Duration::from_millis(wait_timeout), async {
+        match document {
+            Some(html) => document::load(page, html).await?,
+            None => {
+                page.goto(navigate_params).await?;
+            }
+        }
+        let loaded = after_load.await?;
+        match wait_until {
+            WaitUntil::Load => wait_for_ready_state(page, "complete").await,
+            WaitUntil::DomContentLoaded => wait_for_ready_state(page, "interactive").await,
+            WaitUntil::NetworkIdle0 => network_idle.as_mut().unwrap().wait(0).await,
+            WaitUntil::NetworkIdle2 => network_idle.as_mut().unwrap().wait(2).await,
+            WaitUntil::Selector(selector) => wait_for_selector(page, selector).await,
+            WaitUntil::Function(expression) => wait_for_function(page, expression).await,
+            WaitUntil::Delay(ms) => sleep(Duration::from_millis(*ms)).await,
+        }
+        Ok::<T, WorkerError>(loaded)
+    })
+    .await
+    .map_err(|_| WorkerError::Timeout(wait_timeout, wait_until.to_string()))?
+}
+
+/// Runs the page on virtual time, so timers and animations finish
+/// without spending the same amount of wall-clock time.
+///
+/// Start it once the navigation committed, the budget counts from there.
+pub struct VirtualTime {
+    budget: u64,
+    expired: EventStream<EventVirtualTimeBudgetExpired>,
+}
+
+impl VirtualTime {
+    pub async fn start(page: &Page, budget: u64) -> Result<Self, WorkerError> {
+        let expired = page
+            .event_listener::<EventVirtualTimeBudgetExpired>()
+            .await?;
+        page.execute(SetVirtualTimePolicyParams {
+            policy: VirtualTimePolicy::PauseIfNetworkFetchesPending,
+            budget: Some(budget as f64),
+            max_virtual_time_task_starvation_count: None,
+            initial_virtual_time: None,
+        })
+        .await?;
+
+        Ok(Self { budget, expired })
+    }
+
+    /// Waits for the budget to run out, up to `deadline`, the end of the
+    /// `wait_timeout` milliseconds the navigation started with.
+    pub async fn expired(
+        mut self,
+        deadline: Instant,
+        wait_timeout: u64,
+    ) -> Result<(), WorkerError> {
+        timeout_at(deadline.into(), self.expired.next())
+            .await
+            .map(|_| ())
+            .map_err(|_| {
+                WorkerError::Timeout(
+                    wait_timeout,
+                    format!("virtual time budget {}ms", self.budget),
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_str() {
+        let cases = [
+            ("load", Some(WaitUntil::Load)),
+            ("domcontentloaded", Some(WaitUntil::DomContentLoaded)),
+            ("networkidle0", Some(WaitUntil::NetworkIdle0)),
+            ("networkidle2", Some(WaitUntil::NetworkIdle2)),
+            (
+                "selector:#main",
+                Some(WaitUntil::Selector("#main".to_owned())),
+            ),
+            // only the first colon separates the prefix
+            (
+                "selector:a:hover",
+                Some(WaitUntil::Selector("a:hover".to_owned())),
+            ),
+            (
+                "function:window.ready === true",
+                Some(WaitUntil::Function("window.ready === true".to_owned())),
+            ),
+            ("delay:1500", Some(WaitUntil::Delay(1500))),
+            ("", None),
+            ("Load", None),
+            ("idle", None),
+            ("selector:", None),
+            ("function:", None),
+            ("delay:", None),
+            ("delay:-1", None),
+            ("delay:1s", None),
+            ("timeout:100", None),
+        ];
+        for (s, expected) in cases {
+            assert_eq!(s.parse::<WaitUntil>().ok(), expected, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn error_names_the_param() {
+        let err = "networkidle".parse::<WaitUntil>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"invalid parameter "wait_until" "networkidle""#
+        );
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for s in [
+            "load",
+            "networkidle2",
+            "selector:a:hover",
+            "function:document.fonts.status == 'loaded'",
+            "delay:0",
+        ] {
+            assert_eq!(s.parse::<WaitUntil>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn caps_wait_timeout() {
+        assert!(validate_timeout(DEFAULT_WAIT_TIMEOUT).is_ok());
+        assert!(validate_timeout(MAX_WAIT_TIMEOUT).is_ok());
+        assert!(matches!(
+            validate_timeout(MAX_WAIT_TIMEOUT + 1),
+            Err(WorkerError::InvalidParam { ref name, .. }) if name == "wait_timeout"
+        ));
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::OnceLock;
+
+use ab_glyph::{FontArc, PxScale};
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use imageproc::drawing::{draw_text_mut, text_size};
+use tide::log::info;
+
+use crate::config::{DAL_OP_MAP, SERVER_CONFIG};
+use crate::error::WorkerError;
+use crate::util::hash::sha1_hex;
+
+static WATERMARKS: OnceLock<HashMap<String, Watermark>> = OnceLock::new();
+
+/// Corner or center of the image the watermark is placed in.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Position {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+    Center,
+}
+
+/// Watermark of a bucket, composited onto screenshots before they are stored.
+/// With both `image` and `text` the text goes below the image.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WatermarkConfig {
+    /// png or jpeg in the bucket storage
+    pub image: Option<String>,
+    pub text: Option<String>,
+    /// ttf or otf in the bucket storage, needed for `text`
+    pub font: Option<String>,
+    /// pixels
+    #[serde(default = "default_font_size")]
+    pub font_size: u32,
+    /// `#rrggbb`
+    #[serde(default = "default_color")]
+    pub color: String,
+    #[serde(default)]
+    pub position: Position,
+    /// percent
+    #[serde(default = "default_opacity")]
+    pub opacity: u8,
+    /// pixels between the watermark and the image edges
+    #[serde(default = "default_margin")]
+    pub margin: u32,
+    /// applied unless a request turns it off
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// requests may turn it on or off with `watermark`
+    #[serde(default)]
+    pub overridable: bool,
+}
+
+/// A bucket watermark, rendered once at startup.
+pub struct Watermark {
+    pub config: WatermarkConfig,
+    /// image and text with the opacity applied
+    mark: RgbaImage,
+    /// sha1 of `mark`, changes with the image or font files behind the config
+    pub digest: String,
+}
+
+impl Watermark {
+    /// The watermark to apply for a request to `bucket`. `requested` may only
+    /// differ from the bucket `enabled` when the bucket is `overridable`.
+    pub fn resolve(
+        bucket: &str,
+        requested: Option<bool>,
+    ) -> Result<Option<&'static Watermark>, WorkerError> {
+        let watermark = WATERMARKS
+            .get()
+            .and_then(|watermarks| watermarks.get(bucket));
+        let enabled = watermark
+            .map(|watermark| watermark.config.enabled)
+            .unwrap_or(false);
+        let overridable = watermark
+            .map(|watermark| watermark.config.overridable)
+            .unwrap_or(false);
+        match requested {
+            Some(requested) if requested != enabled && !overridable => {
+                Err(WorkerError::InvalidParam {
+                    name: "watermark".to_owned(),
+                    value: requested.to_string(),
+                })
+            }
+            _ => Ok(watermark.filter(|_| requested.unwrap_or(enabled))),
+        }
+    }
+
+    /// Composites the watermark onto `image`, scaled down if it doesn't fit
+    /// within the margins.
+    pub fn apply(&self, image: &mut RgbaImage) {
+        let margin = self.config.margin;
+        let room = (
+            image.width().saturating_sub(2 * margin),
+            image.height().saturating_sub(2 * margin),
+        );
+        if room.0 == 0 || room.1 == 0 {
+            return;
+        }
+
+        let (width, height) = self.mark.dimensions();
+        let ratio = f64::min(
+            f64::from(room.0) / f64::from(width),
+            f64::from(room.1) / f64::from(height),
+        );
+        let scaled;
+        let mark = match ratio < 1.0 {
+            true => {
+                scaled = imageops::resize(
+                    &self.mark,
+                    ((f64::from(width) * ratio) as u32).max(1),
+                    ((f64::from(height) * ratio) as u32).max(1),
+                    FilterType::Lanczos3,
+                );
+                &scaled
+            }
+            false => &self.mark,
+        };
+
+        let (x, y) =
+            self.config
+                .position
+                .origin(image.dimensions(), mark.dimensions(), i64::from(margin));
+        imageops::overlay(image, mark, x, y);
+    }
+}
+
+impl fmt::Debug for Watermark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Watermark").field(&self.config).finish()
+    }
+}
+
+impl Position {
+    /// Top left corner of a `mark` sized watermark on a `canvas` sized image.
+    fn origin(&self, canvas: (u32, u32), mark: (u32, u32), margin: i64) -> (i64, i64) {
+        let right = i64::from(canvas.0) - i64::from(mark.0) - margin;
+        let bottom = i64::from(canvas.1) - i64::from(mark.1) - margin;
+        match self {
+            Position::TopLeft => (margin, margin),
+            Position::TopRight => (right, margin),
+            Position::BottomLeft => (margin, bottom),
+            Position::BottomRight => (right, bottom),
+            Position::Center => (
+                (i64::from(canvas.0) - i64::from(mark.0)) / 2,
+                (i64::from(canvas.1) - i64::from(mark.1)) / 2,
+            ),
+        }
+    }
+}
+
+fn invalid_data(bucket: &str, reason: impl fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("bucket {} watermark: {}", bucket, reason),
+    )
+}
+
+fn parse_color(color: &str) -> Option<Rgba<u8>> {
+    let hex = color.strip_prefix('#').filter(|hex| hex.len() == 6)?;
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some(Rgba([channel(0)?, channel(2)?, channel(4)?, 255]))
+}
+
+fn render_text(text: &str, font: &FontArc, font_size: u32, color: Rgba<u8>) -> RgbaImage {
+    let scale = PxScale::from(font_size as f32);
+    let (width, height) = text_size(scale, font, text);
+    // transparent in the text color, so anti-aliased edges don't darken
+    let mut canvas = RgbaImage::from_pixel(
+        width.max(1),
+        height.max(1),
+        Rgba([color[0], color[1], color[2], 0]),
+    );
+    draw_text_mut(&mut canvas, color, 0, 0, scale, font, text);
+    canvas
+}
+
+/// `top` above `bottom`, both centered.
+fn stack(top: RgbaImage, bottom: RgbaImage, gap: u32) -> RgbaImage {
+    let width = top.width().max(bottom.width());
+    let mut canvas = RgbaImage::new(width, top.height() + gap + bottom.height());
+    imageops::overlay(&mut canvas, &top, i64::from((width - top.width()) / 2), 0);
+    imageops::overlay(
+        &mut canvas,
+        &bottom,
+        i64::from((width - bottom.width()) / 2),
+        i64::from(top.height() + gap),
+    );
+    canvas
+}
+
+async fn render(bucket: &str, config: &WatermarkConfig) -> io::Result<RgbaImage> {
+    let op = DAL_OP_MAP.get(bucket).unwrap();
+
+    let image = match &config.image {
+        Some(path) => Some(
+            image::load_from_memory(&op.read(path).await?)
+                .map_err(|err| invalid_data(bucket, err))?
+                .to_rgba8(),
+        ),
+        None => None,
+    };
+
+    let text = match (&config.text, &config.font) {
+        (Some(text), Some(font)) => {
+            let font = FontArc::try_from_vec(op.read(font).await?)
+                .map_err(|err| invalid_data(bucket, err))?;
+            let color = parse_color(&config.color)
+                .ok_or_else(|| invalid_data(bucket, format!("color {:?}", config.color)))?;
+            Some(render_text(text, &font, config.font_size, color))
+        }
+        (Some(_), None) => return Err(invalid_data(bucket, "text needs a font")),
+        (None, _) => None,
+    };
+
+    let mut mark = match (image, text) {
+        (Some(image), Some(text)) => stack(image, text, config.font_size / 4),
+        (Some(image), None) => image,
+        (None, Some(text)) => text,
+        (None, None) => return Err(invalid_data(bucket, "needs an image or text")),
+    };
+    let opacity = u16::from(config.opacity.min(100));
+    for pixel in mark.pixels_mut() {
+        pixel[3] = (u16::from(pixel[3]) * opacity / 100) as u8;
+    }
+    Ok(mark)
+}
+
+/// Reads and renders the `watermark` of every bucket from the bucket
+/// storage. Runs once at startup.
+pub async fn load() -> io::Result<()> {
+    let mut watermarks = HashMap::new();
+    for (bucket, config) in &SERVER_CONFIG.buckets {
+        if let Some(config) = &config.watermark {
+            let mark = render(bucket, config).await?;
+            info!(
+                "bucket {:#} watermark {}x{}",
+                bucket,
+                mark.width(),
+                mark.height()
+            );
+            watermarks.insert(
+                bucket.clone(),
+                Watermark {
+                    config: config.clone(),
+                    digest: sha1_hex(mark.as_raw()),
+                    mark,
+                },
+            );
+        }
+    }
+    let _ = WATERMARKS.set(watermarks);
+    Ok(())
+}
+
+fn default_font_size() -> u32 {
+    24
+}
+
+fn default_color() -> String {
+    "#ffffff".to_owned()
+}
+
+fn default_opacity() -> u8 {
+    50
+}
+
+fn default_margin() -> u32 {
+    16
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origin_keeps_the_margin_to_the_edges() {
+        let (canvas, mark) = ((200, 100), (50, 20));
+        assert_eq!(Position::TopLeft.origin(canvas, mark, 10), (10, 10));
+        assert_eq!(Position::TopRight.origin(canvas, mark, 10), (140, 10));
+        assert_eq!(Position::BottomLeft.origin(canvas, mark, 10), (10, 70));
+        assert_eq!(Position::BottomRight.origin(canvas, mark, 10), (140, 70));
+        // no margin in the center
+        assert_eq!(Position::Center.origin(canvas, mark, 10), (75, 40));
+    }
+
+    #[test]
+    fn origin_of_a_mark_larger_than_the_canvas() {
+        // negative, the overlay clips what sticks out
+        assert_eq!(
+            Position::BottomRight.origin((40, 40), (60, 50), 8),
+            (-28, -18)
+        );
+        assert_eq!(Position::Center.origin((40, 40), (60, 50), 8), (-10, -5));
+    }
+
+    #[test]
+    fn parses_hex_colors() {
+        assert_eq!(parse_color("#ff8000"), Some(Rgba([255, 128, 0, 255])));
+        assert_eq!(parse_color("#FFFFFF"), Some(Rgba([255, 255, 255, 255])));
+        assert_eq!(parse_color("ff8000"), None);
+        assert_eq!(parse_color("#fff"), None);
+        assert_eq!(parse_color("#ff80001"), None);
+        assert_eq!(parse_color("#gg0000"), None);
+        // six bytes but not six hex digits
+        assert_eq!(parse_color("#ééé"), None);
+    }
+}